@@ -7,4 +7,12 @@ fn main() {
     // .unwrap();
 
     println!("cargo::rustc-link-arg-tests=-Tembedded-test.x");
+
+    // the firmware reads the wifi credentials from the environment
+    println!("cargo::rerun-if-changed=build.rs");
+    println!("cargo::rerun-if-env-changed=WIFI_SSID");
+    println!("cargo::rerun-if-env-changed=WIFI_PASSWORD");
+    if std::env::var_os("CARGO_FEATURE_WIFI").is_some() && std::env::var_os("WIFI_SSID").is_none() {
+        println!("cargo::warning=WIFI_SSID is not set, the watch won't be able to sync the time");
+    }
 }
@@ -0,0 +1,551 @@
+//! Extra readings from the BMA423 accelerometer.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embedded_hal::i2c::I2c;
+use esp_hal::macros::ram;
+use futures::Stream;
+use time::Date;
+
+use crate::sticky_signal::StickySignal;
+
+/// The i2c address of the BMA423 on the watchy.
+pub const BMA423_ADDRESS: u8 = 0x18;
+
+const CHIP_ID: u8 = 0x00;
+/// The value of the chip id register on a BMA423.
+const BMA423_CHIP_ID: u8 = 0x13;
+/// The first of the six acceleration data registers, x y z in lsb msb order.
+const ACC_DATA: u8 = 0x12;
+/// The feature interrupts that have fired, cleared when read. The bits
+/// match [`INT1_MAP`].
+const INT_STATUS_0: u8 = 0x1C;
+/// The first of the four step counter registers, in lsb first order.
+const STEP_COUNTER: u8 = 0x1E;
+const TEMPERATURE: u8 = 0x22;
+/// The sample rate and filtering, with the rate in the low nibble.
+const ACC_CONF: u8 = 0x40;
+const ACC_CONF_ODR: u8 = 0x0F;
+/// The electrical behaviour of the INT2 pin.
+const INT2_IO_CTRL: u8 = 0x54;
+/// Drive INT2 as a push-pull, active high output.
+const INT2_OUTPUT_ACTIVE_HIGH: u8 = (1 << 3) | (1 << 1);
+/// Which feature interrupts go to the INT1 pin.
+const INT1_MAP: u8 = 0x56;
+/// Which data interrupts go to the INT1 and INT2 pins.
+const INT_MAP_DATA: u8 = 0x58;
+/// The data ready on INT2 bit in [`INT_MAP_DATA`].
+const INT_MAP_DATA_INT2_DRDY: u8 = 1 << 6;
+/// The step counter bit in [`INT1_MAP`].
+const INT1_MAP_STEP_COUNTER: u8 = 1 << 1;
+/// The wrist tilt bit in [`INT1_MAP`].
+const INT1_MAP_WRIST_TILT: u8 = 1 << 3;
+/// The feature configuration, which has to be read and written whole.
+const FEATURES_IN: u8 = 0x5E;
+const FEATURES_LEN: usize = 64;
+/// Where the step counter enable bit lives in the feature configuration.
+const STEP_COUNTER_ENABLE_OFFSET: usize = 0x37;
+const STEP_COUNTER_ENABLE: u8 = 1 << 4;
+/// Where the wrist tilt enable bit lives in the feature configuration.
+const WRIST_TILT_ENABLE_OFFSET: usize = 0x3A;
+const WRIST_TILT_ENABLE: u8 = 1 << 0;
+/// Raw acceleration per g, with 12 bit samples in the 2g range.
+const RAW_PER_G: i16 = 1024;
+/// The temperature register reads this when there is no valid sample.
+const TEMPERATURE_INVALID: i8 = i8::MIN;
+/// A temperature register reading of zero is 23°C.
+const TEMPERATURE_OFFSET: i8 = 23;
+
+/// The latest temperature from the accelerometer, in °C.
+pub static ACCEL_TEMPERATURE: StickySignal<CriticalSectionRawMutex, i8, 4> =
+    StickySignal::new_with_name("accel_temperature");
+
+/// Steps taken today, updated by the accelerometer task.
+pub static DAILY_STEPS: StickySignal<CriticalSectionRawMutex, u32, 4> =
+    StickySignal::new_with_name("daily_steps");
+
+/// Pulsed by the accelerometer interrupt, so the step count can be read
+/// as it changes rather than waiting for the next minute.
+pub static ACCEL_INTERRUPT: StickySignal<CriticalSectionRawMutex, (), 1> =
+    StickySignal::new_with_name("accel_interrupt");
+
+/// Set a feature's enable bit and route its interrupt to INT1.
+///
+/// This should be done after the driver has loaded the feature config, and
+/// leaves the other features and interrupt mappings as they were.
+fn enable_feature<I2C: I2c>(
+    i2c: &mut I2C,
+    offset: usize,
+    enable: u8,
+    interrupt: u8,
+) -> Result<(), I2C::Error> {
+    let mut features = [0; FEATURES_LEN];
+    i2c.write_read(BMA423_ADDRESS, &[FEATURES_IN], &mut features)?;
+    features[offset] |= enable;
+
+    let mut write = [0; FEATURES_LEN + 1];
+    write[0] = FEATURES_IN;
+    write[1..].copy_from_slice(&features);
+    i2c.write(BMA423_ADDRESS, &write)?;
+
+    let mut map = [0];
+    i2c.write_read(BMA423_ADDRESS, &[INT1_MAP], &mut map)?;
+    i2c.write(BMA423_ADDRESS, &[INT1_MAP, map[0] | interrupt])
+}
+
+/// Turn on the step counter and route its interrupt to INT1.
+pub fn enable_step_counter<I2C: I2c>(i2c: &mut I2C) -> Result<(), I2C::Error> {
+    enable_feature(
+        i2c,
+        STEP_COUNTER_ENABLE_OFFSET,
+        STEP_COUNTER_ENABLE,
+        INT1_MAP_STEP_COUNTER,
+    )
+}
+
+/// Turn on wrist tilt detection and route its interrupt to INT1.
+pub fn enable_wrist_tilt<I2C: I2c>(i2c: &mut I2C) -> Result<(), I2C::Error> {
+    enable_feature(
+        i2c,
+        WRIST_TILT_ENABLE_OFFSET,
+        WRIST_TILT_ENABLE,
+        INT1_MAP_WRIST_TILT,
+    )
+}
+
+/// Which feature interrupts have fired since the status was last read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptStatus(u8);
+
+impl InterruptStatus {
+    pub fn step_counter(&self) -> bool {
+        self.0 & INT1_MAP_STEP_COUNTER != 0
+    }
+
+    pub fn wrist_tilt(&self) -> bool {
+        self.0 & INT1_MAP_WRIST_TILT != 0
+    }
+}
+
+/// Read and clear the feature interrupt status.
+pub fn read_interrupt_status<I2C: I2c>(i2c: &mut I2C) -> Result<InterruptStatus, I2C::Error> {
+    let mut status = [0];
+    i2c.write_read(BMA423_ADDRESS, &[INT_STATUS_0], &mut status)?;
+    Ok(InterruptStatus(status[0]))
+}
+
+/// How far the watch has to be turned towards the wearer after a wrist
+/// tilt before it counts as raised.
+///
+/// The accelerometer fires on any flick of the wrist, so the face is
+/// checked afterwards to filter out tilts that aren't a glance.
+///
+/// This is only a post-filter in software: the chip's own wrist tilt
+/// detection isn't tuned, so this can reject tilts the chip reports, but
+/// no setting makes the chip report more of them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum TiltSensitivity {
+    /// The face has to be almost flat, facing up.
+    Low,
+    #[default]
+    Medium,
+    /// Any tilt that leaves the face pointing roughly upwards.
+    High,
+}
+
+impl TiltSensitivity {
+    /// The smallest upwards component of gravity that counts as raised,
+    /// in the units of [`read_accel_raw`].
+    pub fn min_face_up(&self) -> i16 {
+        match self {
+            TiltSensitivity::Low => RAW_PER_G * 8 / 10,
+            TiltSensitivity::Medium => RAW_PER_G * 6 / 10,
+            TiltSensitivity::High => RAW_PER_G * 4 / 10,
+        }
+    }
+
+    /// Whether a sample from [`read_accel_raw`] shows the face raised
+    /// towards the wearer.
+    pub fn is_raised(&self, (_, _, z): (i16, i16, i16)) -> bool {
+        // -z is face up
+        z.saturating_neg() >= self.min_face_up()
+    }
+}
+
+/// Read the total steps counted since the accelerometer was powered on.
+pub fn read_steps<I2C: I2c>(i2c: &mut I2C) -> Result<u32, I2C::Error> {
+    let mut count = [0; 4];
+    i2c.write_read(BMA423_ADDRESS, &[STEP_COUNTER], &mut count)?;
+    Ok(u32::from_le_bytes(count))
+}
+
+/// Marks [`STEP_BASELINE`] as valid, since rtc memory is garbage after a
+/// power on reset.
+const STEP_BASELINE_MAGIC: u32 = 0x7374_6570;
+
+/// The [`DailySteps`] state, kept through deep sleep.
+#[derive(Clone, Copy)]
+struct PersistedSteps {
+    magic: u32,
+    julian_day: i32,
+    baseline: u32,
+    carried: u32,
+    last: u32,
+}
+
+#[ram(rtc_slow, persistent)]
+static mut STEP_BASELINE: PersistedSteps = PersistedSteps {
+    magic: 0,
+    julian_day: 0,
+    baseline: 0,
+    carried: 0,
+    last: 0,
+};
+
+/// Turns the accelerometer's running step total into steps per day.
+///
+/// The total only resets when the accelerometer does, so the total at the
+/// start of each day is kept as a baseline and subtracted. If the total
+/// goes backwards the steps counted so far are carried over.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DailySteps {
+    /// The local day being counted, or `None` before the first reading.
+    day: Option<Date>,
+    /// The total at the start of the day, or at the last reset.
+    baseline: u32,
+    /// Steps taken today before the last reset.
+    carried: u32,
+    /// The most recent total.
+    last: u32,
+}
+
+impl DailySteps {
+    pub const fn new() -> Self {
+        Self {
+            day: None,
+            baseline: 0,
+            carried: 0,
+            last: 0,
+        }
+    }
+
+    /// Restore the state saved by [`DailySteps::persist`], if any.
+    pub fn load() -> Self {
+        let persisted = unsafe { STEP_BASELINE };
+        if persisted.magic != STEP_BASELINE_MAGIC {
+            return Self::new();
+        }
+
+        match Date::from_julian_day(persisted.julian_day) {
+            Ok(day) => Self {
+                day: Some(day),
+                baseline: persisted.baseline,
+                carried: persisted.carried,
+                last: persisted.last,
+            },
+            Err(_) => Self::new(),
+        }
+    }
+
+    /// Save the state to rtc memory so the count survives deep sleep.
+    pub fn persist(&self) {
+        let Some(day) = self.day else {
+            return;
+        };
+
+        let persisted = PersistedSteps {
+            magic: STEP_BASELINE_MAGIC,
+            julian_day: day.to_julian_day(),
+            baseline: self.baseline,
+            carried: self.carried,
+            last: self.last,
+        };
+        unsafe { STEP_BASELINE = persisted };
+    }
+
+    /// Record a reading of the running total on `today`, in local time,
+    /// returning the steps taken today.
+    pub fn update(&mut self, total: u32, today: Date) -> u32 {
+        if self.day != Some(today) {
+            *self = Self {
+                day: Some(today),
+                baseline: total,
+                carried: 0,
+                last: total,
+            };
+            return 0;
+        }
+
+        if total < self.last {
+            defmt::info!("step counter reset, carrying over {} steps", self.steps());
+            self.carried = self.steps();
+            self.baseline = 0;
+        }
+
+        self.last = total;
+        self.steps()
+    }
+
+    /// The steps taken today, as of the last reading.
+    pub fn steps(&self) -> u32 {
+        self.carried + self.last.saturating_sub(self.baseline)
+    }
+}
+
+/// The steps taken today, waiting for the first reading if there isn't one.
+pub async fn steps() -> u32 {
+    DAILY_STEPS.wait_for("steps", Some).await
+}
+
+/// The smallest sideways component of gravity that picks an orientation.
+/// Below this the watch is lying too flat to tell.
+const ORIENTATION_MIN: i16 = RAW_PER_G / 2;
+/// How much more gravity has to point along a new orientation than the
+/// current one before switching, so small movements don't flip it.
+const ORIENTATION_HYSTERESIS: i16 = RAW_PER_G / 4;
+
+/// The latest orientation of the watch, updated by the accelerometer task.
+pub static ORIENTATION: StickySignal<CriticalSectionRawMutex, Orientation, 4> =
+    StickySignal::new_with_name("orientation");
+
+/// Which way up the watch is being held, from the direction of gravity.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Orientation {
+    /// Upright, with the buttons on the sides.
+    #[default]
+    Normal,
+    RotatedLeft,
+    RotatedRight,
+    Upside,
+}
+
+impl Orientation {
+    const ALL: [Orientation; 4] = [
+        Orientation::Normal,
+        Orientation::RotatedLeft,
+        Orientation::RotatedRight,
+        Orientation::Upside,
+    ];
+
+    /// How much of gravity points down in this orientation, for a sample
+    /// from [`read_accel_raw`].
+    fn component(&self, (x, y, _): (i16, i16, i16)) -> i16 {
+        // +x is vertical, +y is rotated left
+        match self {
+            Orientation::Normal => x,
+            Orientation::Upside => x.saturating_neg(),
+            Orientation::RotatedLeft => y,
+            Orientation::RotatedRight => y.saturating_neg(),
+        }
+    }
+
+    /// The orientation closest to a sample from [`read_accel_raw`], or
+    /// `None` if the watch is lying too flat to tell.
+    pub fn from_vector(sample: (i16, i16, i16)) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .max_by_key(|orientation| orientation.component(sample))
+            .filter(|orientation| orientation.component(sample) >= ORIENTATION_MIN)
+    }
+}
+
+/// Follows the orientation of the watch, only switching once it has
+/// clearly been turned.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OrientationTracker {
+    current: Orientation,
+}
+
+impl OrientationTracker {
+    pub const fn new() -> Self {
+        Self {
+            current: Orientation::Normal,
+        }
+    }
+
+    pub fn current(&self) -> Orientation {
+        self.current
+    }
+
+    /// Record a sample from [`read_accel_raw`], returning the orientation.
+    ///
+    /// The orientation is kept while the watch is flat, and only changes
+    /// when gravity points along the new one by a clear margin.
+    pub fn update(&mut self, sample: (i16, i16, i16)) -> Orientation {
+        if let Some(candidate) = Orientation::from_vector(sample) {
+            let margin = candidate
+                .component(sample)
+                .saturating_sub(self.current.component(sample));
+            if candidate != self.current && margin >= ORIENTATION_HYSTERESIS {
+                self.current = candidate;
+            }
+        }
+
+        self.current
+    }
+}
+
+/// Pulsed by the INT2 pin whenever a new sample is ready, while a
+/// [`SampleStream`] is running.
+pub static ACCEL_DATA_READY: StickySignal<CriticalSectionRawMutex, (), 1> =
+    StickySignal::new_with_name("accel_data_ready");
+
+/// How often the accelerometer takes a sample.
+///
+/// The accelerometer itself only draws a few µA more at higher rates. The
+/// real cost is the esp32 waking to read every sample: at 25Hz it still
+/// spends most of its time idle, but from 100Hz up it barely sleeps, and
+/// nothing deep sleeps while a stream is running, so the battery lasts
+/// hours rather than days.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SampleRate {
+    Hz25,
+    #[default]
+    Hz50,
+    Hz100,
+    Hz200,
+    Hz400,
+}
+
+impl SampleRate {
+    /// The value of the rate bits in [`ACC_CONF`].
+    fn odr(&self) -> u8 {
+        match self {
+            SampleRate::Hz25 => 0x06,
+            SampleRate::Hz50 => 0x07,
+            SampleRate::Hz100 => 0x08,
+            SampleRate::Hz200 => 0x09,
+            SampleRate::Hz400 => 0x0A,
+        }
+    }
+}
+
+/// Continuous acceleration samples, in g, paced by the data ready
+/// interrupt on INT2.
+///
+/// INT2 is separate from the feature interrupts on INT1, so taps and the
+/// step counter keep working while this runs. Dropping it puts the sample
+/// rate back and stops the interrupt.
+///
+/// ```no_run
+/// # async fn example(i2c: impl embedded_hal::i2c::I2c) {
+/// use futures::StreamExt;
+/// use watchy_rs::accel::{SampleRate, SampleStream};
+///
+/// let samples = SampleStream::new(i2c, SampleRate::Hz50).unwrap().stream();
+/// futures::pin_mut!(samples);
+/// while let Some((x, y, z)) = samples.next().await {
+///     defmt::info!("x: {} y: {} z: {}", x, y, z);
+/// }
+/// # }
+/// ```
+pub struct SampleStream<I2C: I2c> {
+    i2c: I2C,
+    /// The [`ACC_CONF`] value to put back when the stream is dropped.
+    previous_conf: u8,
+}
+
+impl<I2C: I2c> SampleStream<I2C> {
+    /// Set the sample rate and start the data ready interrupt.
+    pub fn new(mut i2c: I2C, rate: SampleRate) -> Result<Self, I2C::Error> {
+        let mut conf = [0];
+        i2c.write_read(BMA423_ADDRESS, &[ACC_CONF], &mut conf)?;
+        let previous_conf = conf[0];
+
+        i2c.write(
+            BMA423_ADDRESS,
+            &[ACC_CONF, (previous_conf & !ACC_CONF_ODR) | rate.odr()],
+        )?;
+        i2c.write(BMA423_ADDRESS, &[INT2_IO_CTRL, INT2_OUTPUT_ACTIVE_HIGH])?;
+
+        let mut map = [0];
+        i2c.write_read(BMA423_ADDRESS, &[INT_MAP_DATA], &mut map)?;
+        i2c.write(
+            BMA423_ADDRESS,
+            &[INT_MAP_DATA, map[0] | INT_MAP_DATA_INT2_DRDY],
+        )?;
+
+        defmt::info!("streaming accelerometer samples at {}", rate);
+        Ok(Self { i2c, previous_conf })
+    }
+
+    /// Wait for the next sample, as `(x, y, z)` in g.
+    pub async fn next(&mut self) -> Result<(f32, f32, f32), I2C::Error> {
+        ACCEL_DATA_READY.wait("sample stream").await;
+        let (x, y, z) = read_accel_raw(&mut self.i2c)?;
+        let g = |raw: i16| f32::from(raw) / f32::from(RAW_PER_G);
+        Ok((g(x), g(y), g(z)))
+    }
+
+    /// Turn this into a stream of samples. Samples that fail to read are
+    /// skipped.
+    pub fn stream(self) -> impl Stream<Item = (f32, f32, f32)> {
+        futures::stream::unfold(self, |mut this| async move {
+            loop {
+                match this.next().await {
+                    Ok(sample) => return Some((sample, this)),
+                    Err(_) => defmt::warn!("failed to read accelerometer sample"),
+                }
+            }
+        })
+    }
+}
+
+impl<I2C: I2c> Drop for SampleStream<I2C> {
+    fn drop(&mut self) {
+        let mut map = [0];
+        let result = self
+            .i2c
+            .write_read(BMA423_ADDRESS, &[INT_MAP_DATA], &mut map)
+            .and_then(|_| {
+                self.i2c.write(
+                    BMA423_ADDRESS,
+                    &[INT_MAP_DATA, map[0] & !INT_MAP_DATA_INT2_DRDY],
+                )
+            })
+            .and_then(|_| {
+                self.i2c
+                    .write(BMA423_ADDRESS, &[ACC_CONF, self.previous_conf])
+            });
+
+        if result.is_err() {
+            defmt::warn!("failed to stop the accelerometer stream");
+        }
+    }
+}
+
+/// Read the BMA423 die temperature in °C, or `None` if it isn't ready.
+///
+/// The sensor has a resolution of 1°C but is only accurate to a few degrees,
+/// and measures the die rather than the air, so it reads warmer when worn.
+/// It's good for rough ambient readings and temperature compensation.
+pub fn read_temperature<I2C: I2c>(i2c: &mut I2C) -> Result<Option<i8>, I2C::Error> {
+    let mut raw = [0];
+    i2c.write_read(BMA423_ADDRESS, &[TEMPERATURE], &mut raw)?;
+
+    let raw = raw[0] as i8;
+    if raw == TEMPERATURE_INVALID {
+        return Ok(None);
+    }
+
+    Ok(Some(raw.saturating_add(TEMPERATURE_OFFSET)))
+}
+
+/// Whether the device at [`BMA423_ADDRESS`] identifies as a BMA423.
+pub fn check_chip_id<I2C: I2c>(i2c: &mut I2C) -> Result<bool, I2C::Error> {
+    let mut id = [0];
+    i2c.write_read(BMA423_ADDRESS, &[CHIP_ID], &mut id)?;
+    Ok(id[0] == BMA423_CHIP_ID)
+}
+
+/// Read a single raw acceleration sample as `(x, y, z)`.
+///
+/// This reads the data registers directly, so it works without an
+/// initialised driver, for example during the self test.
+pub fn read_accel_raw<I2C: I2c>(i2c: &mut I2C) -> Result<(i16, i16, i16), I2C::Error> {
+    let mut data = [0; 6];
+    i2c.write_read(BMA423_ADDRESS, &[ACC_DATA], &mut data)?;
+
+    // samples are 12 bits, left aligned
+    let axis = |i: usize| i16::from_le_bytes([data[i], data[i + 1]]) >> 4;
+    Ok((axis(0), axis(2), axis(4)))
+}
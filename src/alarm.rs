@@ -0,0 +1,115 @@
+//! Daily alarm, backed by the PCF8563 external RTC.
+//!
+//! The alarm is programmed into the RTC so that it can wake us from deep sleep
+//! via [`WakeupCause::ExternalRtcAlarm`](crate::WakeupCause::ExternalRtcAlarm).
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{with_timeout, Duration};
+use embedded_hal::i2c::I2c;
+
+use crate::pcf8563::Pcf8563;
+use crate::sticky_signal::StickySignal;
+use crate::GlobalTime;
+
+/// Whether the alarm is currently ringing, read by the display.
+pub static ALARM_RINGING: StickySignal<CriticalSectionRawMutex, bool, 4> =
+    StickySignal::new_with_name("alarm_ringing");
+
+/// How long the alarm stays on the display if no button is pressed.
+pub const RING_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// A local time of day to ring at.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct Alarm {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl Alarm {
+    /// The same time of day, moved by `minutes` and wrapped around midnight.
+    fn shifted(self, minutes: i32) -> Self {
+        let total =
+            (i32::from(self.hour) * 60 + i32::from(self.minute) + minutes).rem_euclid(24 * 60);
+        Self {
+            hour: (total / 60) as u8,
+            minute: (total % 60) as u8,
+        }
+    }
+}
+
+/// The current local offset from utc, in minutes.
+fn local_offset_minutes(global_time: &GlobalTime) -> i32 {
+    global_time.now().offset().whole_minutes().into()
+}
+
+/// Program and enable the alarm.
+///
+/// The RTC keeps utc, so the local time is converted with the current
+/// timezone offset. Set the alarm again after the offset changes, such as
+/// at a daylight saving transition, or it rings an hour out.
+pub fn set_alarm<I2C: I2c>(
+    rtc: &mut Pcf8563<I2C>,
+    global_time: &GlobalTime,
+    alarm: Alarm,
+) -> Result<(), I2C::Error> {
+    let utc = alarm.shifted(-local_offset_minutes(global_time));
+    defmt::info!("setting alarm for {} ({} utc)", alarm, utc);
+    rtc.set_alarm(utc.hour, utc.minute)
+}
+
+/// Read back the alarm in local time, or `None` if it is disabled.
+pub fn get_alarm<I2C: I2c>(
+    rtc: &mut Pcf8563<I2C>,
+    global_time: &GlobalTime,
+) -> Result<Option<Alarm>, I2C::Error> {
+    let offset = local_offset_minutes(global_time);
+    Ok(rtc
+        .get_alarm()?
+        .map(|(hour, minute)| Alarm { hour, minute }.shifted(offset)))
+}
+
+/// Disable the alarm.
+pub fn disable_alarm<I2C: I2c>(rtc: &mut Pcf8563<I2C>) -> Result<(), I2C::Error> {
+    defmt::info!("disabling alarm");
+    rtc.disable_alarm()
+}
+
+/// Ring the alarm after waking due to the RTC.
+///
+/// This acknowledges the alarm on the RTC so it can fire again tomorrow,
+/// shows the notification on the display, and buzzes the motor. The
+/// notification stays up until [`dismiss`] is called, see
+/// [`dismiss_alarm_task`].
+pub async fn ring<I2C: I2c>(rtc: &mut Pcf8563<I2C>) -> Result<(), I2C::Error> {
+    rtc.clear_alarm_flag()?;
+    ALARM_RINGING.signal(true);
+    crate::request_redraw();
+
+    crate::vibration::play(crate::vibration::ALARM).await;
+
+    Ok(())
+}
+
+/// Stop showing the alarm, if it is ringing.
+pub fn dismiss() {
+    if ALARM_RINGING.signal_if_changed(false) {
+        defmt::info!("alarm dismissed");
+        crate::request_redraw();
+    }
+}
+
+/// Dismiss the alarm on the next button press, or after [`RING_TIMEOUT`].
+#[embassy_executor::task]
+pub async fn dismiss_alarm_task() {
+    match crate::buttons::subscribe() {
+        Ok(mut buttons) => {
+            let _ = with_timeout(RING_TIMEOUT, buttons.next_message_pure()).await;
+        }
+        Err(_) => {
+            defmt::warn!("no button subscribers left, dismissing the alarm on a timer");
+            embassy_time::Timer::after(RING_TIMEOUT).await;
+        }
+    }
+
+    dismiss();
+}
@@ -1,15 +1,448 @@
 //! Battery status using the ADC.
 
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::{
-    analog::adc::{Adc, AdcCalLine, AdcConfig, Attenuation},
-    gpio::{ErasedPin, GpioPin, Input, Level, Pull},
+    analog::adc::{Adc, AdcCalLine, AdcChannel, AdcConfig, AdcPin, Attenuation},
+    gpio::{AnalogPin, ErasedPin, GpioPin, Input, Level, Pull},
     peripherals::ADC1,
     prelude::nb,
 };
 
+use crate::sticky_signal::StickySignal;
+
+/// While plugged in, the battery counts as full at or above this voltage (in mV).
+pub const FULL_CHARGE_MV: u32 = 4150;
+
+/// Whether the watch is plugged in, either charging or full, as of the
+/// last reading.
+///
+/// Only signalled when it changes, so waiters see the plug and unplug edges.
+pub static CHARGING: StickySignal<CriticalSectionRawMutex, bool, 2> =
+    StickySignal::new_with_name("charging");
+
+/// The charge state, as of the last reading. Only signalled when it changes.
+pub static CHARGE_STATE: StickySignal<CriticalSectionRawMutex, ChargeState, 2> =
+    StickySignal::new_with_name("charge_state");
+
+/// Whether the battery is being charged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ChargeState {
+    /// Running on battery.
+    #[default]
+    Discharging,
+    /// Plugged in and charging.
+    Charging,
+    /// Plugged in with the battery topped up.
+    Full,
+}
+
+impl ChargeState {
+    /// Combine a charge pin reading with the battery voltage (in mV).
+    pub fn from_readings(charge_pin_active: bool, battery_voltage: u32) -> Self {
+        match (charge_pin_active, battery_voltage >= FULL_CHARGE_MV) {
+            (false, _) => ChargeState::Discharging,
+            (true, false) => ChargeState::Charging,
+            (true, true) => ChargeState::Full,
+        }
+    }
+
+    /// Whether the watch is plugged in, either charging or full.
+    pub fn is_plugged_in(&self) -> bool {
+        !matches!(self, ChargeState::Discharging)
+    }
+}
+
+/// The latest battery reading, published by [`battery_monitor`].
+pub static BATTERY_STATUS: StickySignal<CriticalSectionRawMutex, BatteryStatus, 4> =
+    StickySignal::new_with_name("battery_status");
+
+/// The latest smoothed charge percentage, published by [`battery_monitor`].
+pub static BATTERY_PERCENTAGE: StickySignal<CriticalSectionRawMutex, u8, 4> =
+    StickySignal::new_with_name("battery_percentage");
+
+/// The default weight (in %) given to new samples when smoothing the percentage.
+pub const DEFAULT_SMOOTHING_ALPHA: u8 = 20;
+
+/// Converts a raw ADC reading (in mV) into the battery voltage (in mV).
+///
+/// The battery is sampled through a voltage divider, so the reading
+/// is scaled back up using the algo in the watchy firmware.
+pub fn scale_voltage(raw: u16) -> u32 {
+    VoltageDivider::WATCHY.scale(raw)
+}
+
+/// The resistor divider between the battery and the ADC pin.
+///
+/// The resistances can be in any unit, as long as both are the same, so
+/// they can be copied straight from the schematic (usually kΩ).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct VoltageDivider {
+    /// The resistor between the battery and the ADC pin.
+    pub r_top: u32,
+    /// The resistor between the ADC pin and ground.
+    pub r_bottom: u32,
+}
+
+impl VoltageDivider {
+    /// The divider on the watchy, 100kΩ over 360kΩ.
+    pub const WATCHY: Self = Self::new(100, 360);
+
+    pub const fn new(r_top: u32, r_bottom: u32) -> Self {
+        Self { r_top, r_bottom }
+    }
+
+    /// Converts a reading on the ADC pin (in mV) into the battery voltage (in mV).
+    pub fn scale(&self, raw: u16) -> u32 {
+        let voltage = raw as f32 * ((self.r_bottom + self.r_top) as f32 / self.r_bottom as f32);
+        voltage as u32
+    }
+
+    /// Converts a battery voltage (in mV) into the voltage on the ADC pin
+    /// (in mV), rounded up. This is the inverse of [`VoltageDivider::scale`].
+    pub fn divide(&self, voltage: u32) -> u32 {
+        (voltage * self.r_bottom).div_ceil(self.r_bottom + self.r_top)
+    }
+}
+
+impl Default for VoltageDivider {
+    fn default() -> Self {
+        Self::WATCHY
+    }
+}
+
+/// The ADC attenuation used for the battery pin unless another is given.
+pub const DEFAULT_ATTENUATION: Attenuation = Attenuation::Attenuation11dB;
+
+/// The highest voltage (in mV) the ADC can measure at an attenuation.
+///
+/// These are the calibrated ranges documented for the ESP32-S3. Readings
+/// above them saturate or lose accuracy.
+///
+/// Even at 11 dB this is below the 3287 mV a full battery puts on the pin
+/// through the watchy's divider, so readings above about 3960 mV clip.
+pub fn attenuation_max_mv(attenuation: Attenuation) -> u32 {
+    match attenuation {
+        Attenuation::Attenuation0dB => 950,
+        Attenuation::Attenuation2p5dB => 1250,
+        Attenuation::Attenuation6dB => 1750,
+        Attenuation::Attenuation11dB => 3100,
+    }
+}
+
+/// The highest battery voltage (in mV) that can be measured at an
+/// attenuation through a divider. Readings above this clip to it.
+pub fn max_battery_mv(attenuation: Attenuation, divider: VoltageDivider) -> u32 {
+    divider.scale(attenuation_max_mv(attenuation) as u16)
+}
+
+/// The attenuation can't measure a battery voltage through the divider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct AttenuationError {
+    /// The voltage (in mV) the battery puts on the pin.
+    pub required_mv: u32,
+    /// The highest voltage (in mV) the attenuation can measure.
+    pub max_mv: u32,
+}
+
+/// Check that a full battery, seen through the watchy's divider, is within
+/// the range of the given attenuation, so that readings near full don't clip.
+pub fn check_attenuation(attenuation: Attenuation) -> Result<(), AttenuationError> {
+    check_attenuation_with_divider(attenuation, VoltageDivider::WATCHY)
+}
+
+/// Check that a full battery, seen through the given divider, is within
+/// the range of the given attenuation.
+pub fn check_attenuation_with_divider(
+    attenuation: Attenuation,
+    divider: VoltageDivider,
+) -> Result<(), AttenuationError> {
+    check_battery_mv(attenuation, divider, FULL_MV)
+}
+
+/// Check that an empty battery, seen through the given divider, is within
+/// the range of the given attenuation. Below this the driver can't tell
+/// when the battery is running out, so it is refused.
+pub fn check_attenuation_usable(
+    attenuation: Attenuation,
+    divider: VoltageDivider,
+) -> Result<(), AttenuationError> {
+    check_battery_mv(attenuation, divider, EMPTY_MV)
+}
+
+fn check_battery_mv(
+    attenuation: Attenuation,
+    divider: VoltageDivider,
+    battery_mv: u32,
+) -> Result<(), AttenuationError> {
+    // rounded up so we err on the side of clipping
+    let required_mv = divider.divide(battery_mv);
+    let max_mv = attenuation_max_mv(attenuation);
+
+    if required_mv > max_mv {
+        return Err(AttenuationError {
+            required_mv,
+            max_mv,
+        });
+    }
+
+    Ok(())
+}
+
+/// Voltage (in mV) to charge percentage breakpoints for a typical LiPo
+/// cell, in increasing order. Readings are interpolated linearly between
+/// adjacent points.
+///
+/// A LiPo spends most of its life around 3.7-3.9V before falling off
+/// quickly, so this is much flatter in the middle than a straight line.
+pub const LIPO_DISCHARGE_CURVE: [(u32, u8); 12] = [
+    (3400, 0),
+    (3600, 5),
+    (3700, 10),
+    (3750, 20),
+    (3790, 30),
+    (3820, 40),
+    (3850, 50),
+    (3880, 60),
+    (3920, 70),
+    (3980, 80),
+    (4060, 90),
+    (4200, 100),
+];
+
+/// The battery voltage (in mV) considered empty.
+const EMPTY_MV: u32 = LIPO_DISCHARGE_CURVE[0].0;
+/// The battery voltage (in mV) considered full.
+const FULL_MV: u32 = LIPO_DISCHARGE_CURVE[LIPO_DISCHARGE_CURVE.len() - 1].0;
+
+/// Returns the charge percentage for a battery voltage in mV, using the
+/// [`LIPO_DISCHARGE_CURVE`].
+pub fn voltage_to_percentage(voltage: u32) -> u8 {
+    curve_percentage(&LIPO_DISCHARGE_CURVE, voltage)
+}
+
+/// Returns the lowest battery voltage in mV that reads as the given
+/// charge percentage. This is the inverse of [`voltage_to_percentage`].
+pub fn percentage_to_voltage(percentage: u8) -> u32 {
+    curve_voltage(&LIPO_DISCHARGE_CURVE, percentage)
+}
+
+/// Returns the charge percentage for a battery voltage in mV, interpolating
+/// between the `(voltage, percentage)` breakpoints of a discharge curve.
+///
+/// Anything below the first point is its percentage, and anything above
+/// the last point is the last percentage.
+pub fn curve_percentage(curve: &[(u32, u8)], voltage: u32) -> u8 {
+    let Some(&(first_mv, first_percentage)) = curve.first() else {
+        return 0;
+    };
+    if voltage <= first_mv {
+        return first_percentage;
+    }
+
+    for window in curve.windows(2) {
+        let [(low_mv, low_percentage), (high_mv, high_percentage)] = [window[0], window[1]];
+        if voltage < high_mv {
+            let span = u32::from(high_percentage.saturating_sub(low_percentage));
+            let offset = voltage.saturating_sub(low_mv).saturating_mul(span)
+                / high_mv.saturating_sub(low_mv).max(1);
+            return low_percentage.saturating_add(offset.try_into().unwrap_or(u8::MAX));
+        }
+    }
+
+    curve.last().map_or(0, |&(_, percentage)| percentage)
+}
+
+/// Returns the lowest voltage in mV that [`curve_percentage`] reads as
+/// the given percentage on the same curve.
+pub fn curve_voltage(curve: &[(u32, u8)], percentage: u8) -> u32 {
+    let Some(&(first_mv, first_percentage)) = curve.first() else {
+        return 0;
+    };
+    if percentage <= first_percentage {
+        return first_mv;
+    }
+
+    for window in curve.windows(2) {
+        let [(low_mv, low_percentage), (high_mv, high_percentage)] = [window[0], window[1]];
+        if percentage <= high_percentage {
+            let span = u32::from(high_percentage.saturating_sub(low_percentage)).max(1);
+            let offset = u32::from(percentage - low_percentage)
+                .saturating_mul(high_mv.saturating_sub(low_mv))
+                .div_ceil(span);
+            return low_mv + offset;
+        }
+    }
+
+    curve.last().map_or(0, |&(voltage, _)| voltage)
+}
+
+/// The charge pin has to rise above this (in mV) to count as charging, see
+/// [`ChargeDetector`].
+pub const CHARGING_ON_MV: u32 = 3100;
+/// The charge pin has to fall below this (in mV) to stop counting as
+/// charging, see [`ChargeDetector`].
+pub const CHARGING_OFF_MV: u32 = 2900;
+
+/// Turns charge pin readings into a charging state, with hysteresis so that
+/// readings hovering between [`CHARGING_OFF_MV`] and [`CHARGING_ON_MV`]
+/// don't flicker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChargeDetector {
+    on_mv: u32,
+    off_mv: u32,
+    active: bool,
+}
+
+impl ChargeDetector {
+    /// A detector using [`CHARGING_ON_MV`] and [`CHARGING_OFF_MV`].
+    pub const fn new() -> Self {
+        Self::with_thresholds(CHARGING_ON_MV, CHARGING_OFF_MV)
+    }
+
+    /// A detector that turns on above `on_mv` and off below `off_mv`.
+    pub const fn with_thresholds(on_mv: u32, off_mv: u32) -> Self {
+        Self {
+            on_mv,
+            off_mv,
+            active: false,
+        }
+    }
+
+    /// Feed in a charge pin reading (in mV), returning whether the
+    /// battery is now charging.
+    pub fn update(&mut self, charge_voltage: u32) -> bool {
+        if charge_voltage > self.on_mv {
+            self.active = true;
+        } else if charge_voltage < self.off_mv {
+            self.active = false;
+        }
+        self.active
+    }
+
+    /// The current state, as of the last reading.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+impl Default for ChargeDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How long to wait for an ADC conversion before giving up.
+const ADC_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Why reading the battery failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum BatteryError {
+    /// The ADC didn't finish a conversion within the timeout.
+    AdcWouldBlockTimeout,
+    /// The ADC reported an error during the conversion.
+    Adc,
+}
+
+impl From<nb::Error<()>> for BatteryError {
+    fn from(error: nb::Error<()>) -> Self {
+        match error {
+            nb::Error::WouldBlock => BatteryError::AdcWouldBlockTimeout,
+            nb::Error::Other(()) => BatteryError::Adc,
+        }
+    }
+}
+
+/// How many samples a [`DischargeEstimator`] needs before it will estimate.
+const MIN_DISCHARGE_SAMPLES: usize = 3;
+
+/// Estimates how long the battery will last by fitting a line to the
+/// last `N` voltage readings.
+///
+/// The estimate is rough, since the discharge curve isn't linear, but it
+/// is good enough for a "time remaining" readout.
+pub struct DischargeEstimator<const N: usize> {
+    samples: heapless::Deque<(Instant, u32), N>,
+}
+
+impl<const N: usize> DischargeEstimator<N> {
+    pub const fn new() -> Self {
+        Self {
+            samples: heapless::Deque::new(),
+        }
+    }
+
+    /// Record a battery voltage (in mV), replacing the oldest sample when
+    /// full. Charging makes the earlier samples useless, so they are cleared.
+    pub fn record(&mut self, at: Instant, voltage: u32, charging: bool) {
+        if charging {
+            self.samples.clear();
+            return;
+        }
+
+        if self.samples.is_full() {
+            self.samples.pop_front();
+        }
+        let _ = self.samples.push_back((at, voltage));
+    }
+
+    /// How long until the battery reaches empty, or `None` if there aren't
+    /// enough samples yet or the voltage isn't falling.
+    pub fn time_to_empty(&self) -> Option<Duration> {
+        let (start, _) = *self.samples.front()?;
+        let (end, _) = *self.samples.back()?;
+        if self.samples.len() < MIN_DISCHARGE_SAMPLES || end <= start {
+            return None;
+        }
+
+        // least squares fit of voltage against seconds since the first sample
+        let points = || {
+            self.samples
+                .iter()
+                .map(move |(at, voltage)| ((*at - start).as_secs() as f32, *voltage as f32))
+        };
+        let count = self.samples.len() as f32;
+        let mean_t = points().map(|(t, _)| t).sum::<f32>() / count;
+        let mean_v = points().map(|(_, v)| v).sum::<f32>() / count;
+        let (covariance, variance) = points().fold((0.0, 0.0), |(cov, var), (t, v)| {
+            (
+                cov + (t - mean_t) * (v - mean_v),
+                var + (t - mean_t) * (t - mean_t),
+            )
+        });
+        if variance <= 0.0 {
+            return None;
+        }
+
+        // in mV per second, negative while discharging
+        let slope = covariance / variance;
+        if slope >= 0.0 {
+            return None;
+        }
+
+        let now_t = (end - start).as_secs() as f32;
+        let now_v = mean_v + slope * (now_t - mean_t);
+        let remaining = (now_v - EMPTY_MV as f32) / -slope;
+
+        Some(Duration::from_secs(remaining.max(0.0) as u64))
+    }
+}
+
+impl<const N: usize> Default for DischargeEstimator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Represents a battery status.
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct BatteryStatus(u32);
 impl BatteryStatus {
+    /// Create a battery status from a voltage in mV.
+    pub const fn from_voltage(voltage: u32) -> Self {
+        Self(voltage)
+    }
+
     /// Returns the battery voltage in mV.
     pub fn voltage(&self) -> u32 {
         self.0
@@ -17,30 +450,120 @@ impl BatteryStatus {
 
     /// Returns the charge percentage of the battery.
     pub fn percentage(&self) -> u8 {
-        // NOTE: The percentage calculation is linear from 3400 mV to 4200 mV
-        self.0
-            .saturating_sub(3400)
-            .saturating_mul(100)
-            .div_euclid(4200 - 3400)
-            .min(100)
-            .try_into()
-            .unwrap()
+        voltage_to_percentage(self.0)
+    }
+
+    /// Returns the voltage in mV at which [`BatteryStatus::percentage`]
+    /// first reports the given percentage, for tests and calibration.
+    pub fn voltage_for_percentage(percentage: u8) -> u32 {
+        percentage_to_voltage(percentage)
+    }
+}
+
+impl defmt::Format for BatteryStatus {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{}mV ({}%)", self.voltage(), self.percentage())
     }
 }
 
+impl core::fmt::Debug for BatteryStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}mV ({}%)", self.voltage(), self.percentage())
+    }
+}
+
+/// Set up `chrg_pin` to be read through the ADC, at 11 dB since it is
+/// compared against [`CHARGING_ON_MV`] and [`CHARGING_OFF_MV`].
+fn analog_charge_pin<'d, CHRG: AdcChannel + AnalogPin>(
+    chrg_pin: CHRG,
+) -> impl FnOnce(&mut AdcConfig<ADC1>) -> ChargePin<'d, CHRG> {
+    move |config| {
+        ChargePin::Adc(
+            config.enable_pin_with_cal::<CHRG, AdcCalLine<ADC1>>(
+                chrg_pin,
+                Attenuation::Attenuation11dB,
+            ),
+        )
+    }
+}
+
+/// How the charge pin is read, which depends on the board.
+enum ChargePin<'d, CHRG> {
+    /// An analog level, compared against the charging thresholds.
+    Adc(AdcPin<CHRG, ADC1, AdcCalLine<ADC1>>),
+    /// A digital status line, pulled low while charging.
+    Digital(Input<'d, ErasedPin>),
+}
+
 /// Driver to retrieve the battery status.
 ///
 /// The battery voltage sampled using an
 /// [ADC](https://en.wikipedia.org/wiki/Analog-to-digital_converter)
 /// peripheral on the ESP32.
-pub struct BatteryStatusDriver<'d> {
-    adc1_pin: esp_hal::analog::adc::AdcPin<esp_hal::gpio::GpioPin<9>, ADC1, AdcCalLine<ADC1>>,
-    chrg_pin: esp_hal::analog::adc::AdcPin<esp_hal::gpio::GpioPin<10>, ADC1, AdcCalLine<ADC1>>,
-    // chrg_pin: Input<'d, ErasedPin>,
+///
+/// The pins default to the ones used on the watchy, but any pins on ADC1
+/// can be used on other boards.
+pub struct BatteryStatusDriver<'d, BAT = GpioPin<9>, CHRG = GpioPin<10>> {
+    adc1_pin: AdcPin<BAT, ADC1, AdcCalLine<ADC1>>,
+    chrg_pin: ChargePin<'d, CHRG>,
     adc1: Adc<'d, ADC1>,
+    /// The weight (in %) given to each new sample when smoothing.
+    smoothing_alpha: u8,
+    /// The smoothed percentage, in hundredths of a percent.
+    smoothed: Option<u16>,
+    /// The charging state when the smoothed percentage was last updated.
+    smoothed_charging: bool,
+    /// The divider the battery is sampled through.
+    divider: VoltageDivider,
+    /// Debounces the charge pin readings.
+    charge_detector: ChargeDetector,
 }
 impl<'d> BatteryStatusDriver<'d> {
-    /// Setup a new battery status driver.
+    /// Setup a new battery status driver on the watchy's battery and
+    /// charge pins.
+    pub fn new_watchy<P: esp_hal::peripheral::Peripheral<P = ADC1> + 'd>(
+        battery_pin: GpioPin<9>,
+        chrg_pin: GpioPin<10>,
+        adc: P,
+    ) -> Result<Self, AttenuationError> {
+        Self::new(battery_pin, chrg_pin, adc)
+    }
+
+    /// Setup a new battery status driver on the watchy's battery and
+    /// charge pins, sampling the battery with the given attenuation.
+    pub fn new_watchy_with_attenuation<P: esp_hal::peripheral::Peripheral<P = ADC1> + 'd>(
+        battery_pin: GpioPin<9>,
+        chrg_pin: GpioPin<10>,
+        adc: P,
+        attenuation: Attenuation,
+    ) -> Result<Self, AttenuationError> {
+        Self::new_with_attenuation(battery_pin, chrg_pin, adc, attenuation)
+    }
+
+    /// Setup a new battery status driver on the watchy's pins, for boards
+    /// where the charge pin is a clean digital line that is pulled low
+    /// while charging, rather than an analog level.
+    pub fn new_digital_charge<P: esp_hal::peripheral::Peripheral<P = ADC1> + 'd>(
+        battery_pin: GpioPin<9>,
+        chrg_pin: GpioPin<10>,
+        adc: P,
+    ) -> Result<Self, AttenuationError> {
+        Self::with_config(
+            battery_pin,
+            |_| ChargePin::Digital(Input::new(chrg_pin, Pull::Up)),
+            adc,
+            DEFAULT_ATTENUATION,
+            VoltageDivider::WATCHY,
+        )
+    }
+}
+
+impl<'d, BAT, CHRG> BatteryStatusDriver<'d, BAT, CHRG>
+where
+    BAT: AdcChannel + AnalogPin,
+    CHRG: AdcChannel + AnalogPin,
+{
+    /// Setup a new battery status driver. Both pins must be on ADC1.
     ///
     /// # Example
     /// ```no_run
@@ -50,56 +573,265 @@ impl<'d> BatteryStatusDriver<'d> {
     ///     watchy::battery::BatteryStatusDriver::new(pin_sets.battery, peripherals.adc1).unwrap();
     /// ```
     pub fn new<P: esp_hal::peripheral::Peripheral<P = ADC1> + 'd>(
-        battery_pin: GpioPin<9>,
-        chrg_pin: GpioPin<10>,
+        battery_pin: BAT,
+        chrg_pin: CHRG,
+        adc: P,
+    ) -> Result<Self, AttenuationError> {
+        Self::new_with_attenuation(battery_pin, chrg_pin, adc, DEFAULT_ATTENUATION)
+    }
+
+    /// Setup a new battery status driver, sampling the battery pin with the
+    /// given attenuation. Both pins must be on ADC1.
+    ///
+    /// Fails if the attenuation can't measure an empty battery through the
+    /// divider, see [`check_attenuation_usable`]. If it can't measure a full
+    /// one, readings near full clip to [`max_battery_mv`].
+    ///
+    /// The charge pin is always sampled at 11 dB, since it is compared
+    /// against [`CHARGING_ON_MV`] and [`CHARGING_OFF_MV`].
+    pub fn new_with_attenuation<P: esp_hal::peripheral::Peripheral<P = ADC1> + 'd>(
+        battery_pin: BAT,
+        chrg_pin: CHRG,
         adc: P,
-    ) -> Self {
+        attenuation: Attenuation,
+    ) -> Result<Self, AttenuationError> {
+        Self::with_config(
+            battery_pin,
+            analog_charge_pin(chrg_pin),
+            adc,
+            attenuation,
+            VoltageDivider::WATCHY,
+        )
+    }
+
+    /// Setup a new battery status driver for a board with a different
+    /// voltage divider, given the resistance from the battery to the pin
+    /// (`r_top`) and from the pin to ground (`r_bottom`), in the same units.
+    /// Both pins must be on ADC1.
+    ///
+    /// Fails if the default attenuation can't measure an empty battery
+    /// through the divider, see [`BatteryStatusDriver::new_with_attenuation`].
+    pub fn new_with_divider<P: esp_hal::peripheral::Peripheral<P = ADC1> + 'd>(
+        battery_pin: BAT,
+        chrg_pin: CHRG,
+        adc: P,
+        r_top: u32,
+        r_bottom: u32,
+    ) -> Result<Self, AttenuationError> {
+        Self::with_config(
+            battery_pin,
+            analog_charge_pin(chrg_pin),
+            adc,
+            DEFAULT_ATTENUATION,
+            VoltageDivider::new(r_top, r_bottom.max(1)),
+        )
+    }
+
+    /// The charge pin is set up by `chrg_pin`, since only the analog path
+    /// needs the adc config.
+    fn with_config<P: esp_hal::peripheral::Peripheral<P = ADC1> + 'd>(
+        battery_pin: BAT,
+        chrg_pin: impl FnOnce(&mut AdcConfig<ADC1>) -> ChargePin<'d, CHRG>,
+        adc: P,
+        attenuation: Attenuation,
+        divider: VoltageDivider,
+    ) -> Result<Self, AttenuationError> {
+        check_attenuation_usable(attenuation, divider)?;
+        if check_attenuation_with_divider(attenuation, divider).is_err() {
+            defmt::warn!(
+                "battery readings clip above {}mV",
+                max_battery_mv(attenuation, divider)
+            );
+        }
+
         // Create ADC instances
         let mut adc1_config = AdcConfig::new();
-        let adc1_pin = adc1_config.enable_pin_with_cal::<GpioPin<9>, AdcCalLine<ADC1>>(
-            battery_pin,
-            Attenuation::Attenuation11dB,
-        );
-        let chrg_pin = adc1_config.enable_pin_with_cal::<GpioPin<10>, AdcCalLine<ADC1>>(
-            chrg_pin,
-            Attenuation::Attenuation11dB,
-        );
+        let adc1_pin =
+            adc1_config.enable_pin_with_cal::<BAT, AdcCalLine<ADC1>>(battery_pin, attenuation);
+        let chrg_pin = chrg_pin(&mut adc1_config);
         let adc1 = Adc::new(adc, adc1_config);
 
-        // let chrg_pin = Input::new(chrg_pin, Pull::Up);
-
-        Self {
+        Ok(Self {
             adc1_pin,
             adc1,
             chrg_pin,
-        }
+            smoothing_alpha: DEFAULT_SMOOTHING_ALPHA,
+            smoothed: None,
+            smoothed_charging: false,
+            divider,
+            charge_detector: ChargeDetector::new(),
+        })
+    }
+
+    /// Set the weight (in %, clamped to 1-100) given to each new sample by
+    /// [`BatteryStatusDriver::smoothed_percentage`]. Lower values are smoother
+    /// but slower to react.
+    pub fn set_smoothing_alpha(&mut self, alpha: u8) {
+        self.smoothing_alpha = alpha.clamp(1, 100);
+    }
+
+    /// Change the charge pin thresholds (in mV), see [`ChargeDetector`].
+    pub fn set_charging_thresholds(&mut self, on_mv: u32, off_mv: u32) {
+        self.charge_detector = ChargeDetector::with_thresholds(on_mv, off_mv);
     }
 
     /// Retrieve the battery status by sampling the ADC.
-    pub async fn status(&mut self) -> Result<BatteryStatus, ()> {
-        let Ok(voltage) = crate::block_embassy!(self.adc1.read_oneshot(&mut self.adc1_pin)) else {
-            return Err(());
-        };
+    pub async fn status(&mut self) -> Result<BatteryStatus, BatteryError> {
+        let voltage =
+            crate::block_embassy!(self.adc1.read_oneshot(&mut self.adc1_pin), ADC_TIMEOUT)?;
+
+        Ok(BatteryStatus(self.divider.scale(voltage)))
+    }
 
-        // adjust voltage based on the algo in the watchy firmware
-        let voltage = voltage as f32 * ((360.0 + 100.0) / 360.0);
-        let voltage = voltage as u32;
+    /// Retrieve the battery status from the average of several ADC samples,
+    /// which is slower than [`BatteryStatusDriver::status`] but less noisy.
+    ///
+    /// When there are at least three samples, the highest and lowest are
+    /// discarded before averaging.
+    pub async fn status_averaged(&mut self, samples: usize) -> Result<BatteryStatus, BatteryError> {
+        let samples = samples.max(1);
+        let (mut sum, mut min, mut max) = (0u32, u16::MAX, u16::MIN);
+
+        for _ in 0..samples {
+            let voltage =
+                crate::block_embassy!(self.adc1.read_oneshot(&mut self.adc1_pin), ADC_TIMEOUT)?;
+            sum += u32::from(voltage);
+            min = min.min(voltage);
+            max = max.max(voltage);
+            embassy_futures::yield_now().await;
+        }
+
+        let (sum, count) = match samples {
+            0..=2 => (sum, samples as u32),
+            _ => (sum - u32::from(min) - u32::from(max), samples as u32 - 2),
+        };
 
-        Ok(BatteryStatus(voltage))
+        Ok(BatteryStatus(self.divider.scale((sum / count) as u16)))
     }
 
-    /// The battery is charging if the charge pin is low.
+    /// Whether the battery is charging, rather than full or unplugged.
+    /// See [`BatteryStatusDriver::charge_state`].
     pub async fn charging(&mut self) -> bool {
-        // let level = self.chrg_pin.get_level();
-        // defmt::info!("reading charge pin {:?}", level);
-        // level
+        matches!(self.charge_state().await, ChargeState::Charging)
+    }
 
-        let Ok(voltage) = crate::block_embassy!(self.adc1.read_oneshot(&mut self.chrg_pin)) else {
-            return false;
+    /// Whether the battery is charging or full, from the charge pin and
+    /// the battery voltage.
+    ///
+    /// If the battery can't be read while plugged in, it is assumed to be
+    /// charging.
+    pub async fn charge_state(&mut self) -> ChargeState {
+        if !self.charge_pin_active().await {
+            return ChargeState::Discharging;
+        }
+
+        let status = self.status().await;
+        if let Err(e) = status {
+            defmt::warn!("failed to read the battery: {}", e);
+        }
+        charge_state_from(true, &status)
+    }
+
+    /// The charge pin is active while the watch is plugged in.
+    ///
+    /// Each call feeds one reading into the [`ChargeDetector`], so sample
+    /// it once per reading.
+    async fn charge_pin_active(&mut self) -> bool {
+        let chrg_pin = match &mut self.chrg_pin {
+            ChargePin::Adc(pin) => pin,
+            ChargePin::Digital(pin) => return pin.get_level() == Level::Low,
         };
 
-        // over 3000 is charging
-        voltage > 3000
+        match crate::block_embassy!(self.adc1.read_oneshot(chrg_pin), ADC_TIMEOUT) {
+            Ok(voltage) => self.charge_detector.update(voltage.into()),
+            Err(e) => {
+                defmt::warn!("failed to read the charge pin: {}", BatteryError::from(e));
+                // keep the last state rather than flickering off
+                self.charge_detector.is_active()
+            }
+        }
+    }
+
+    /// The charge percentage, smoothed with an exponential moving average
+    /// so that it doesn't bounce around between draws.
+    ///
+    /// Each call feeds `status` into the average. The average is reset when
+    /// `plugged_in` changes, so that it tracks the quick jump in voltage.
+    pub fn smoothed_percentage(&mut self, status: BatteryStatus, plugged_in: bool) -> u8 {
+        let sample = u16::from(status.percentage()) * 100;
+
+        let smoothed = match self.smoothed {
+            Some(previous) if plugged_in == self.smoothed_charging => {
+                let alpha = u32::from(self.smoothing_alpha);
+                let blended =
+                    (u32::from(sample) * alpha + u32::from(previous) * (100 - alpha)) / 100;
+                blended as u16
+            }
+            _ => sample,
+        };
+
+        self.smoothed = Some(smoothed);
+        self.smoothed_charging = plugged_in;
+
+        ((smoothed + 50) / 100) as u8
+    }
+}
+
+/// The charge state from a charge pin reading and a battery reading. If
+/// the battery can't be read while plugged in, it is assumed to be charging.
+fn charge_state_from(
+    charge_pin_active: bool,
+    status: &Result<BatteryStatus, BatteryError>,
+) -> ChargeState {
+    match (charge_pin_active, status) {
+        (false, _) => ChargeState::Discharging,
+        (true, Ok(status)) => ChargeState::from_readings(true, status.voltage()),
+        (true, Err(_)) => ChargeState::Charging,
+    }
+}
+
+/// Sample the battery every `interval`, publishing the readings to
+/// [`BATTERY_STATUS`], [`BATTERY_PERCENTAGE`], [`CHARGE_STATE`] and [`CHARGING`].
+///
+/// This is the only owner of the ADC, so everything else should read the
+/// battery through those signals rather than sampling it themselves.
+#[embassy_executor::task]
+pub async fn battery_monitor(
+    battery_pin: GpioPin<9>,
+    chrg_pin: GpioPin<10>,
+    adc: ADC1,
+    interval: Duration,
+) {
+    let mut battery = match BatteryStatusDriver::new_watchy(battery_pin, chrg_pin, adc) {
+        Ok(battery) => battery,
+        Err(e) => {
+            defmt::error!("can't measure the battery: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        // sample each pin once, so every reading counts once towards the
+        // charge detector and the average
+        let status = battery.status().await;
+        match status {
+            Ok(status) => {
+                defmt::info!("battery is {}", status);
+                BATTERY_STATUS.signal(status);
+            }
+            Err(e) => defmt::warn!("failed to read the battery: {}", e),
+        }
+
+        let state = charge_state_from(battery.charge_pin_active().await, &status);
+        CHARGE_STATE.signal_if_changed(state);
+        CHARGING.signal_if_changed(state.is_plugged_in());
+
+        if let Ok(status) = status {
+            let percentage = battery.smoothed_percentage(status, state.is_plugged_in());
+            BATTERY_PERCENTAGE.signal(percentage);
+        }
+
+        Timer::after(interval).await;
     }
 }
 
@@ -116,8 +848,32 @@ impl<'d> BatteryStatusDriver<'d> {
 ///
 /// - `Ok(t)` if `$e` evaluates to `Ok(t)`
 /// - `Err(e)` if `$e` evaluates to `Err(nb::Error::Other(e))`
+///
+/// If a timeout `Duration` is also given, the error is surfaced as the
+/// `nb::Error` itself, with `nb::Error::WouldBlock` meaning that `$e` was
+/// still blocking when the timeout passed.
 #[macro_export]
 macro_rules! block_embassy {
+    ($e:expr, $timeout:expr) => {{
+        let start = embassy_time::Instant::now();
+        loop {
+            #[allow(unreachable_patterns)]
+            match $e {
+                Err(nb::Error::Other(e)) =>
+                {
+                    #[allow(unreachable_code)]
+                    break Err(nb::Error::Other(e))
+                }
+                Err(nb::Error::WouldBlock) if start.elapsed() > $timeout => {
+                    break Err(nb::Error::WouldBlock);
+                }
+                Err(nb::Error::WouldBlock) => {
+                    embassy_futures::yield_now().await;
+                }
+                Ok(x) => break Ok(x),
+            }
+        }
+    }};
     ($e:expr) => {
         loop {
             #[allow(unreachable_patterns)]
@@ -0,0 +1,216 @@
+//! Binding actions to the buttons.
+//!
+//! The button task calls [`dispatch_event`] for each press, which runs
+//! whatever handler was bound to that button with [`on_press`], or
+//! [`on_long_press`] if it was held.
+//!
+//! Every gesture is also published on [`BUTTON_EVENTS`], so tasks can react
+//! to the buttons without binding a handler, see [`subscribe`].
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::pubsub::{Error as PubSubError, PubSubChannel, Subscriber};
+use embassy_time::Duration;
+
+use crate::Button;
+
+/// How long a button has to be held to count as a long press.
+pub const DEFAULT_LONG_PRESS: Duration = Duration::from_millis(800);
+
+/// An action to run when a button is pressed.
+pub type ButtonHandler = fn(Button);
+
+/// The handler for each button, indexed by the button.
+static HANDLERS: Mutex<CriticalSectionRawMutex, Cell<[Option<ButtonHandler>; 4]>> =
+    Mutex::new(Cell::new([None; 4]));
+
+/// The long press handler for each button, indexed by the button.
+static LONG_HANDLERS: Mutex<CriticalSectionRawMutex, Cell<[Option<ButtonHandler>; 4]>> =
+    Mutex::new(Cell::new([None; 4]));
+
+/// How a button was pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PressKind {
+    /// Released before the long press threshold.
+    Short,
+    /// Held for at least the long press threshold.
+    Long,
+}
+
+/// A finished press of a button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ButtonEvent {
+    pub button: Button,
+    pub kind: PressKind,
+}
+
+/// How long after releasing a button a second press counts as a double press.
+pub const DOUBLE_PRESS_WINDOW: Duration = Duration::from_millis(300);
+
+/// How soon after pressing a button a second one has to go down for the
+/// two to count as a combo.
+pub const COMBO_WINDOW: Duration = Duration::from_millis(150);
+
+/// Everything the buttons can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ButtonGesture {
+    /// A single press of one button.
+    Press(ButtonEvent),
+    /// Two short presses of the same button in quick succession.
+    DoublePress(Button),
+    /// Two buttons pressed together, in the order they went down.
+    Combo(Button, Button),
+}
+
+/// How many gestures are kept for slow subscribers. A subscriber that
+/// falls further behind than this misses the oldest ones, and is told how
+/// many with `WaitResult::Lagged`.
+const BUTTON_EVENT_MSGS: usize = 4;
+/// How many tasks can subscribe at once. Each slot costs a waker, and
+/// [`subscribe`] fails once they're all taken.
+const BUTTON_EVENT_SUBS: usize = 4;
+/// How many publishers can exist at once. The button task publishes
+/// immediately, which doesn't take a slot, so this is left for others.
+const BUTTON_EVENT_PUBS: usize = 1;
+
+/// Every gesture made with the buttons, as they happen.
+pub static BUTTON_EVENTS: PubSubChannel<
+    CriticalSectionRawMutex,
+    ButtonGesture,
+    BUTTON_EVENT_MSGS,
+    BUTTON_EVENT_SUBS,
+    BUTTON_EVENT_PUBS,
+> = PubSubChannel::new();
+
+/// A subscription to [`BUTTON_EVENTS`].
+pub type ButtonSubscriber = Subscriber<
+    'static,
+    CriticalSectionRawMutex,
+    ButtonGesture,
+    BUTTON_EVENT_MSGS,
+    BUTTON_EVENT_SUBS,
+    BUTTON_EVENT_PUBS,
+>;
+
+/// Subscribe to the button gestures, or fail if there are already
+/// too many subscribers.
+pub fn subscribe() -> Result<ButtonSubscriber, PubSubError> {
+    BUTTON_EVENTS.subscriber()
+}
+
+/// Publish a gesture to every subscriber, dropping the oldest for any that
+/// have fallen behind.
+pub fn publish(gesture: ButtonGesture) {
+    BUTTON_EVENTS
+        .immediate_publisher()
+        .publish_immediate(gesture);
+}
+
+/// An action to run for double presses and combos.
+pub type GestureHandler = fn(ButtonGesture);
+
+static GESTURE_HANDLER: Mutex<CriticalSectionRawMutex, Cell<Option<GestureHandler>>> =
+    Mutex::new(Cell::new(None));
+
+/// Run `handler` for every double press and combo, replacing any existing
+/// gesture handler.
+///
+/// Double presses are only detected while there is a gesture handler,
+/// since it delays single presses by [`DOUBLE_PRESS_WINDOW`].
+pub fn on_gesture(handler: GestureHandler) {
+    GESTURE_HANDLER.lock(|cell| cell.set(Some(handler)))
+}
+
+/// Remove the gesture handler, if any.
+pub fn clear_gesture_handler() {
+    GESTURE_HANDLER.lock(|cell| cell.set(None))
+}
+
+/// Whether double presses should be looked for, see [`on_gesture`].
+pub fn detect_double_press() -> bool {
+    GESTURE_HANDLER.lock(|cell| cell.get()).is_some()
+}
+
+/// Run the handler for `gesture`, returning whether there was one.
+///
+/// Single presses go to the button handlers, see [`dispatch_event`].
+pub fn dispatch_gesture(gesture: ButtonGesture) -> bool {
+    if let ButtonGesture::Press(event) = gesture {
+        return dispatch_event(event);
+    }
+
+    match GESTURE_HANDLER.lock(|cell| cell.get()) {
+        Some(handler) => {
+            handler(gesture);
+            true
+        }
+        None => {
+            defmt::debug!("no handler for gesture {}", gesture);
+            false
+        }
+    }
+}
+
+/// Run `handler` whenever `button` is pressed, replacing any existing handler.
+pub fn on_press(button: Button, handler: ButtonHandler) {
+    HANDLERS.lock(|cell| {
+        let mut handlers = cell.get();
+        handlers[button as usize] = Some(handler);
+        cell.set(handlers);
+    })
+}
+
+/// Run `handler` whenever `button` is held for a long press, replacing any
+/// existing long press handler.
+pub fn on_long_press(button: Button, handler: ButtonHandler) {
+    LONG_HANDLERS.lock(|cell| {
+        let mut handlers = cell.get();
+        handlers[button as usize] = Some(handler);
+        cell.set(handlers);
+    })
+}
+
+/// Remove the handlers for `button`, if any.
+pub fn clear_handler(button: Button) {
+    for handlers in [&HANDLERS, &LONG_HANDLERS] {
+        handlers.lock(|cell| {
+            let mut handlers = cell.get();
+            handlers[button as usize] = None;
+            cell.set(handlers);
+        })
+    }
+}
+
+/// Run the handler for `event`, returning whether there was one.
+///
+/// Long presses of buttons without a long press handler fall back to the
+/// normal handler, so holding a button never does nothing.
+pub fn dispatch_event(event: ButtonEvent) -> bool {
+    if event.kind == PressKind::Long {
+        let handler = LONG_HANDLERS.lock(|cell| cell.get()[event.button as usize]);
+        if let Some(handler) = handler {
+            handler(event.button);
+            return true;
+        }
+    }
+
+    dispatch(event.button)
+}
+
+/// Run the handler for `button`, returning whether there was one.
+pub fn dispatch(button: Button) -> bool {
+    // copy the handler out so it can rebind buttons without deadlocking
+    let handler = HANDLERS.lock(|cell| cell.get()[button as usize]);
+
+    match handler {
+        Some(handler) => {
+            handler(button);
+            true
+        }
+        None => {
+            defmt::debug!("no handler for button {}", button);
+            false
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! A minimal clock abstraction for time-dependent primitives.
+//!
+//! Production code uses [`EmbassyClock`], which is zero-sized and simply
+//! delegates to `embassy_time`. Tests can use [`MockClock`] instead, which
+//! only moves forward when told to.
+
+use core::cell::RefCell;
+use core::future::{poll_fn, Future};
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{Duration, Instant, Timer};
+
+/// A source of time that can be slept on.
+pub trait Clock {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// Future that completes once `duration` has passed.
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()>;
+}
+
+/// The real clock, backed by `embassy_time`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmbassyClock;
+
+impl Clock for EmbassyClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> {
+        Timer::after(duration)
+    }
+}
+
+/// A clock that only advances when [`MockClock::advance`] is called.
+///
+/// Only a single sleeper is woken on advance, so this is intended for
+/// tests that have one time-dependent future in flight at a time.
+pub struct MockClock {
+    state: Mutex<CriticalSectionRawMutex, RefCell<(Instant, WakerRegistration)>>,
+}
+
+impl MockClock {
+    /// Create a new clock, starting at zero.
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new((
+                Instant::from_ticks(0),
+                WakerRegistration::new(),
+            ))),
+        }
+    }
+
+    /// Move the clock forward, waking any sleeper.
+    pub fn advance(&self, duration: Duration) {
+        self.state.lock(|cell| {
+            let mut state = cell.borrow_mut();
+            state.0 += duration;
+            state.1.wake();
+        })
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.state.lock(|cell| cell.borrow().0)
+    }
+
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> {
+        let deadline = self.now() + duration;
+        poll_fn(move |cx| {
+            self.state.lock(|cell| {
+                let mut state = cell.borrow_mut();
+                if state.0 >= deadline {
+                    Poll::Ready(())
+                } else {
+                    state.1.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+    }
+}
@@ -1,108 +1,187 @@
-use core::cell::RefCell;
-
-use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
-use embassy_sync::blocking_mutex::Mutex;
-use embedded_graphics::geometry::Point;
-use embedded_graphics::prelude::Primitive;
-use embedded_graphics::primitives::{Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder};
-use embedded_graphics::Drawable;
-use epd_waveshare::color::Color;
-use epd_waveshare::epd1in54::{Display1in54, Epd1in54};
-use epd_waveshare::prelude::WaveshareDisplay;
-
-use esp_hal::clock::Clocks;
+//! A wrapper that keeps the display driver together with its spi device
+//! and delay, so they don't have to be passed around separately.
+//!
+//! The watchy v3 has a 1.54" v2 panel, but other variants use different
+//! waveshare panels, so the wrapper works with any [`Panel`].
+
+use core::convert::Infallible;
+use core::marker::PhantomData;
+
+use embassy_embedded_hal::shared_bus::SpiDeviceError;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions};
+use epd_waveshare::graphics::Display;
+use epd_waveshare::prelude::{Color, WaveshareDisplay};
+use epd_waveshare::{epd1in54, epd1in54_v2, epd2in9_v2};
 use esp_hal::delay::Delay;
-use esp_hal::gpio::{Input, InputPin, Output, OutputPin};
-use esp_hal::peripheral::Peripheral;
-use esp_hal::peripherals::SPI2;
-use esp_hal::prelude::*;
-use esp_hal::spi::master::Spi;
-use esp_hal::spi::FullDuplexMode;
-
-pub struct WatchyDisplay<'a, SPI, P1, P2, P3>
+use esp_hal::gpio::{ErasedPin, GpioPin, Input, Output};
+
+use crate::ui::{BusyPin, BUSY_POLL_US};
+use crate::{DisplayConfig, EpdBus, EpdSpi};
+
+/// A frame buffer for a panel, drawn into with embedded-graphics.
+pub trait PanelBuffer: DrawTarget<Color = Color> + OriginDimensions + Default {
+    /// The raw frame to send to the panel.
+    fn bytes(&self) -> &[u8];
+}
+
+impl<const WIDTH: u32, const HEIGHT: u32, const BWRBIT: bool, const BYTECOUNT: usize> PanelBuffer
+    for Display<WIDTH, HEIGHT, BWRBIT, BYTECOUNT, Color>
+{
+    fn bytes(&self) -> &[u8] {
+        self.buffer()
+    }
+}
+
+/// A waveshare panel model, along with the buffer that matches its size.
+pub trait Panel<SPI, BUSY, DC, RST, DELAY>: WaveshareDisplay<SPI, BUSY, DC, RST, DELAY>
 where
-    P1: OutputPin,
-    P2: OutputPin,
-    P3: InputPin,
+    SPI: embedded_hal::spi::SpiDevice,
+    BUSY: embedded_hal::digital::InputPin,
+    DC: embedded_hal::digital::OutputPin,
+    RST: embedded_hal::digital::OutputPin,
+    DELAY: embedded_hal::delay::DelayNs,
 {
-    epd: Epd1in54<SPI, Input<'a, P3>, Output<'a, P1>, Output<'a, P2>, Delay>,
-    spi: SPI,
-    delay: &'a mut Delay,
+    type Buffer: PanelBuffer;
 }
 
-type DefaultSpi<'a, P> = SpiDevice<'a, NoopRawMutex, Spi<'a, SPI2, FullDuplexMode>, P>;
+impl<SPI, BUSY, DC, RST, DELAY> Panel<SPI, BUSY, DC, RST, DELAY>
+    for epd1in54::Epd1in54<SPI, BUSY, DC, RST, DELAY>
+where
+    SPI: embedded_hal::spi::SpiDevice,
+    BUSY: embedded_hal::digital::InputPin,
+    DC: embedded_hal::digital::OutputPin,
+    RST: embedded_hal::digital::OutputPin,
+    DELAY: embedded_hal::delay::DelayNs,
+{
+    type Buffer = epd1in54::Display1in54;
+}
 
-impl<'a, P1, P2, P3, P> WatchyDisplay<'a, DefaultSpi<'a, P>, P1, P2, P3>
+impl<SPI, BUSY, DC, RST, DELAY> Panel<SPI, BUSY, DC, RST, DELAY>
+    for epd1in54_v2::Epd1in54<SPI, BUSY, DC, RST, DELAY>
 where
-    P1: OutputPin,
-    P2: OutputPin,
-    P3: InputPin,
-    P: embedded_hal::digital::OutputPin,
+    SPI: embedded_hal::spi::SpiDevice,
+    BUSY: embedded_hal::digital::InputPin,
+    DC: embedded_hal::digital::OutputPin,
+    RST: embedded_hal::digital::OutputPin,
+    DELAY: embedded_hal::delay::DelayNs,
 {
-    pub fn new(
-        spi2: SPI2,
-        clocks: &Clocks,
-        delay: &'a mut Delay,
-        pin_spi_sck: impl Peripheral<P = impl OutputPin> + 'a,
-        pin_spi_miso: impl Peripheral<P = impl InputPin> + 'a,
-        _pin_spi_mosi: impl Peripheral<P = impl OutputPin>,
-        pin_spi_edp_cs: P,
-        pin_edp_dc: Output<'a, P1>,
-        pin_edp_reset: Output<'a, P2>,
-        pin_edp_busy: Input<'a, P3>,
-    ) -> Result<WatchyDisplay<'a, DefaultSpi<'a, P>, P1, P2, P3>, ()> {
-        let spi = Spi::new(spi2, 20.MHz(), esp_hal::spi::SpiMode::Mode0, clocks)
-            .with_sck(pin_spi_sck)
-            .with_miso(pin_spi_miso);
-
-        let spi = Mutex::new(RefCell::new(spi));
-
-        let mut spi = SpiDevice::new(&spi, pin_spi_edp_cs);
-        let epd = Epd1in54::new(
-            &mut spi_dev,
-            pin_edp_busy.into(),
-            pin_edp_dc.into(),
-            pin_edp_reset.into(),
-            delay,
-            None,
-        )
-        .unwrap();
+    // the v2 panel has the same resolution, so shares the v1 buffer
+    type Buffer = epd1in54::Display1in54;
+}
 
-        Ok(WatchyDisplay { epd, spi, delay })
+impl<SPI, BUSY, DC, RST, DELAY> Panel<SPI, BUSY, DC, RST, DELAY>
+    for epd2in9_v2::Epd2in9<SPI, BUSY, DC, RST, DELAY>
+where
+    SPI: embedded_hal::spi::SpiDevice,
+    BUSY: embedded_hal::digital::InputPin,
+    DC: embedded_hal::digital::OutputPin,
+    RST: embedded_hal::digital::OutputPin,
+    DELAY: embedded_hal::delay::DelayNs,
+{
+    type Buffer = epd2in9_v2::Display2in9;
+}
+
+/// The display driver along with everything needed to talk to it.
+pub struct WatchyDisplay<EPD, SPI, BUSY, DC, RST, DELAY> {
+    epd: EPD,
+    spi: SPI,
+    delay: DELAY,
+    /// The pins are owned by the driver.
+    pins: PhantomData<(BUSY, DC, RST)>,
+}
+
+/// A [`WatchyDisplay`] for a 1.54" v1 panel.
+pub type Watchy1in54<SPI, BUSY, DC, RST, DELAY> =
+    WatchyDisplay<epd1in54::Epd1in54<SPI, BUSY, DC, RST, DELAY>, SPI, BUSY, DC, RST, DELAY>;
+
+/// A [`WatchyDisplay`] for a 1.54" v2 panel.
+pub type Watchy1in54V2<SPI, BUSY, DC, RST, DELAY> =
+    WatchyDisplay<epd1in54_v2::Epd1in54<SPI, BUSY, DC, RST, DELAY>, SPI, BUSY, DC, RST, DELAY>;
+
+/// A [`WatchyDisplay`] for a 2.9" v2 panel.
+pub type Watchy2in9V2<SPI, BUSY, DC, RST, DELAY> =
+    WatchyDisplay<epd2in9_v2::Epd2in9<SPI, BUSY, DC, RST, DELAY>, SPI, BUSY, DC, RST, DELAY>;
+
+/// The display on a watchy v3, as set up by [`WatchyV3Display::setup`].
+pub type WatchyV3Display<'a> = Watchy1in54V2<
+    EpdSpi<'a>,
+    BusyPin<Input<'static, ErasedPin>>,
+    Output<'static, ErasedPin>,
+    Output<'static, ErasedPin>,
+    Delay,
+>;
+
+impl<'a> WatchyV3Display<'a> {
+    /// Set up the display on the watchy's pins, see [`crate::setup_epd`].
+    pub fn setup(
+        bus: &'a EpdBus,
+        cs: GpioPin<33>,
+        dc: GpioPin<34>,
+        reset: GpioPin<35>,
+        busy: GpioPin<36>,
+        mut delay: Delay,
+        config: DisplayConfig,
+    ) -> Result<Self, SpiDeviceError<esp_hal::spi::Error, Infallible>> {
+        let (epd, spi) = crate::setup_epd(bus, cs, dc, reset, busy, &mut delay, config)?;
+        Ok(Self {
+            epd,
+            spi,
+            delay,
+            pins: PhantomData,
+        })
     }
 }
 
-impl<'a, SPI, P1, P2, P3> WatchyDisplay<'a, SPI, P1, P2, P3>
+impl<EPD, SPI, BUSY, DC, RST, DELAY> WatchyDisplay<EPD, SPI, BUSY, DC, RST, DELAY>
 where
-    P1: OutputPin,
-    P2: OutputPin,
-    P3: InputPin,
     SPI: embedded_hal::spi::SpiDevice,
+    BUSY: embedded_hal::digital::InputPin,
+    DC: embedded_hal::digital::OutputPin,
+    RST: embedded_hal::digital::OutputPin,
+    DELAY: embedded_hal::delay::DelayNs,
+    EPD: Panel<SPI, BUSY, DC, RST, DELAY>,
 {
+    /// Initialise the panel on an spi device and pins that are already
+    /// configured. This tries once, unlike [`WatchyV3Display::setup`].
+    pub fn new(
+        mut spi: SPI,
+        busy: BUSY,
+        dc: DC,
+        reset: RST,
+        mut delay: DELAY,
+    ) -> Result<Self, SPI::Error> {
+        let epd = EPD::new(&mut spi, busy, dc, reset, &mut delay, Some(BUSY_POLL_US))?;
+        Ok(Self {
+            epd,
+            spi,
+            delay,
+            pins: PhantomData,
+        })
+    }
+
+    /// The driver, spi device and delay, for functions that take them
+    /// separately.
+    pub fn parts(&mut self) -> (&mut EPD, &mut SPI, &mut DELAY) {
+        (&mut self.epd, &mut self.spi, &mut self.delay)
+    }
+
+    /// Draw [`crate::ui::test_pattern`] and put the panel to sleep.
     pub fn draw_test(&mut self) -> Result<(), SPI::Error> {
-        // Use display graphics from embedded-graphics
-        let mut display = Display1in54::default();
-
-        // Use embedded graphics for drawing a line
-        let style = PrimitiveStyleBuilder::new()
-            .stroke_color(Color::Black)
-            .stroke_width(1)
-            .build();
-        let _ = Line::new(Point::new(0, 120), Point::new(0, 295))
-            .into_styled(style)
-            .draw(&mut display);
-        let _ = Circle::with_center(Point::new(50, 50), 50)
-            .into_styled(PrimitiveStyle::with_fill(Color::White))
-            .draw(&mut display);
-
-        // Display updated frame
+        let display: EPD::Buffer = crate::ui::test_pattern();
+
         self.epd
-            .update_frame(&mut self.spi, &display.buffer(), &mut self.delay)?;
+            .update_frame(&mut self.spi, display.bytes(), &mut self.delay)?;
         self.epd.display_frame(&mut self.spi, &mut self.delay)?;
+        self.epd.sleep(&mut self.spi, &mut self.delay)
+    }
+
+    /// See [`crate::show_message`].
+    pub fn show_message(&mut self, message: &str) -> Result<(), SPI::Error> {
+        crate::show_message(&mut self.epd, &mut self.spi, &mut self.delay, message)
+    }
 
-        // Set the EPD to sleep
-        self.epd.sleep(&mut self.spi, &mut self.delay)?;
-        Ok(())
+    /// See [`crate::clear_ghosting`].
+    pub fn clear_ghosting(&mut self) -> Result<(), SPI::Error> {
+        crate::clear_ghosting(&mut self.epd, &mut self.spi, &mut self.delay)
     }
 }
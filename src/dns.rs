@@ -1,39 +1,91 @@
-use embedded_nal_async::IpAddr;
-use esp_println::println;
+use embedded_nal_async::{AddrType, IpAddr, Ipv4Addr};
 
-/// A simple dns resolver that only supports IP addresses
-pub struct StaticDns;
+/// A simple dns resolver that supports IP addresses and a static
+/// list of hostnames.
+///
+/// ```no_run
+/// use embedded_nal_async::{IpAddr, Ipv4Addr};
+///
+/// static HOSTS: &[(&str, IpAddr)] = &[(
+///     "pool.ntp.org",
+///     IpAddr::V4(Ipv4Addr::new(185, 83, 169, 27)),
+/// )];
+/// let dns = watchy_rs::StaticDns::new(HOSTS);
+/// ```
+#[derive(Clone, Copy, Default)]
+pub struct StaticDns<'a> {
+    hosts: &'a [(&'a str, IpAddr)],
+}
 
-impl embedded_nal_async::Dns for StaticDns {
-    type Error = ();
+impl<'a> StaticDns<'a> {
+    /// Create a resolver for the given `(hostname, address)` pairs.
+    pub const fn new(hosts: &'a [(&'a str, IpAddr)]) -> Self {
+        Self { hosts }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub enum DnsError {
+    /// The host is not an IP address or a known hostname.
+    NotFound,
+    /// Only ipv4 lookups are supported.
+    Unsupported,
+}
+
+impl embedded_nal_async::Dns for StaticDns<'_> {
+    type Error = DnsError;
 
     async fn get_host_by_name(
         &self,
-        _host: &str,
-        _addr_type: embedded_nal_async::AddrType,
-    ) -> Result<embedded_nal_async::IpAddr, Self::Error> {
-        Ok(IpAddr::from(parse_ip4v(_host)))
+        host: &str,
+        addr_type: AddrType,
+    ) -> Result<IpAddr, Self::Error> {
+        if let AddrType::IPv6 = addr_type {
+            return Err(DnsError::Unsupported);
+        }
+
+        if let Some(ip) = parse_ip4v(host) {
+            return Ok(IpAddr::from(ip));
+        }
+
+        self.hosts
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(host))
+            .map(|(_, addr)| *addr)
+            .ok_or_else(|| {
+                defmt::warn!("no dns entry for {}", host);
+                DnsError::NotFound
+            })
     }
 
     async fn get_host_by_address(
         &self,
-        _addr: embedded_nal_async::IpAddr,
-        _result: &mut [u8],
+        addr: IpAddr,
+        result: &mut [u8],
     ) -> Result<usize, Self::Error> {
-        println!("{:?}", _addr);
-        Err(())
+        let (name, _) = self
+            .hosts
+            .iter()
+            .find(|(_, a)| *a == addr)
+            .ok_or(DnsError::NotFound)?;
+
+        let len = name.len().min(result.len());
+        result[..len].copy_from_slice(&name.as_bytes()[..len]);
+        Ok(len)
     }
 }
 
-fn parse_ip4v(input: &str) -> [u8; 4] {
-    let (p1, input) = input.split_once('.').unwrap();
-    let (p2, input) = input.split_once('.').unwrap();
-    let (p3, p4) = input.split_once('.').unwrap();
-
-    [
-        p1.parse().unwrap(),
-        p2.parse().unwrap(),
-        p3.parse().unwrap(),
-        p4.parse().unwrap(),
-    ]
+fn parse_ip4v(input: &str) -> Option<Ipv4Addr> {
+    let mut parts = input.split('.');
+    let mut octets = [0; 4];
+    for octet in octets.iter_mut() {
+        *octet = parts.next()?.parse().ok()?;
+    }
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let [a, b, c, d] = octets;
+    Some(Ipv4Addr::new(a, b, c, d))
 }
@@ -0,0 +1,255 @@
+//! Four level grayscale for the 1.54" v2 panel.
+//!
+//! The panel's SSD1681 controller picks a waveform for each pixel from
+//! the matching bits in its two frame RAMs, so with a custom LUT those two
+//! bitplanes give four shades. epd-waveshare only knows the built-in black
+//! and white LUTs, so the frame and LUT are sent here with raw commands.
+//!
+//! Every grayscale draw is a full refresh with the slower 4 gray waveform,
+//! which Waveshare's demo puts at about 2.3s, so it costs more than the
+//! mix of full and quick refreshes the 1-bit path uses, and the stopwatch
+//! loses its partial refreshes. The time each draw takes on the actual
+//! panel is measured as `grayscale` in [`crate::refresh_metrics`].
+
+use embedded_graphics::{pixelcolor::Gray2, prelude::*};
+use epd_waveshare::{epd1in54_v2::HEIGHT, epd1in54_v2::WIDTH, prelude::DisplayRotation};
+use esp_hal::gpio::{ErasedPin, GpioPin, Input, Level, Output, Pull};
+use esp_hal::peripheral::Peripheral;
+
+use crate::ui::{BusyPin, BUSY_POLL_US};
+use crate::DisplayConfig;
+
+/// The bytes in one bitplane of the frame.
+const PLANE_LEN: usize = (WIDTH / 8 * HEIGHT) as usize;
+
+/// The 4 gray waveform, in the SSD168x LUT layout: the voltages for each
+/// of the four shades, the timing groups, then the frame rate and gate
+/// settings. Taken from Waveshare's 4 gray demo for the same controller
+/// family.
+const LUT_GRAY4: [u8; 153] = [
+    // VS L0-L4, one row per shade plus VCOM
+    0x00, 0x60, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x20, 0x60, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x28, 0x60, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x2A, 0x60, 0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    // TP, SR and RP for groups 0-11
+    0x00, 0x02, 0x00, 0x05, 0x14, 0x00, 0x00, //
+    0x1E, 0x1E, 0x00, 0x00, 0x00, 0x00, 0x01, //
+    0x00, 0x02, 0x00, 0x05, 0x14, 0x00, 0x00, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    // frame rate and gate/source timing
+    0x24, 0x22, 0x22, 0x22, 0x23, 0x32, 0x00, 0x00, 0x00,
+];
+
+/// The end option, gate, source and VCOM voltages that go with [`LUT_GRAY4`].
+const LUT_EOPT: u8 = 0x22;
+const LUT_VGH: u8 = 0x17;
+const LUT_VSH: [u8; 3] = [0x41, 0xAE, 0x32];
+const LUT_VCOM: u8 = 0x28;
+
+mod command {
+    pub const GATE_VOLTAGE: u8 = 0x03;
+    pub const SOURCE_VOLTAGE: u8 = 0x04;
+    pub const DATA_ENTRY_MODE: u8 = 0x11;
+    pub const MASTER_ACTIVATION: u8 = 0x20;
+    pub const DISPLAY_UPDATE_CONTROL_2: u8 = 0x22;
+    pub const WRITE_RAM_BW: u8 = 0x24;
+    pub const WRITE_RAM_RED: u8 = 0x26;
+    pub const WRITE_VCOM: u8 = 0x2C;
+    pub const WRITE_LUT: u8 = 0x32;
+    pub const END_OPTION: u8 = 0x3F;
+    pub const RAM_X_RANGE: u8 = 0x44;
+    pub const RAM_Y_RANGE: u8 = 0x45;
+    pub const RAM_X_COUNTER: u8 = 0x4E;
+    pub const RAM_Y_COUNTER: u8 = 0x4F;
+}
+
+/// Run the display update with the LUT in the register, rather than
+/// loading one from OTP.
+const UPDATE_WITH_LUT_REGISTER: u8 = 0xC7;
+
+/// A frame with four shades per pixel, split into the two bitplanes the
+/// panel expects.
+///
+/// The high bit of each pixel's luma goes to the black and white RAM and
+/// the low bit to the red RAM, which is what [`LUT_GRAY4`] is laid out for.
+pub struct Gray4Buffer {
+    high: [u8; PLANE_LEN],
+    low: [u8; PLANE_LEN],
+    rotation: DisplayRotation,
+}
+
+impl Gray4Buffer {
+    /// Start from a black and white frame, so the 1-bit parts of the face
+    /// are only drawn once. `rotation` must match the one the frame was
+    /// drawn with, and is used for anything drawn in gray on top.
+    pub fn from_mono(frame: &[u8], rotation: DisplayRotation) -> Self {
+        // white is 1 in the black and white buffer, and luma 0b11 here
+        let mut high = [0; PLANE_LEN];
+        high.copy_from_slice(&frame[..PLANE_LEN]);
+        Self {
+            high,
+            low: high,
+            rotation,
+        }
+    }
+
+    /// The byte index and bit mask of a point, in panel coordinates.
+    fn position(&self, point: Point) -> Option<(usize, u8)> {
+        let (width, height) = (WIDTH as i32, HEIGHT as i32);
+        let (x, y) = match self.rotation {
+            DisplayRotation::Rotate0 => (point.x, point.y),
+            DisplayRotation::Rotate90 => (width - 1 - point.y, point.x),
+            DisplayRotation::Rotate180 => (width - 1 - point.x, height - 1 - point.y),
+            DisplayRotation::Rotate270 => (point.y, height - 1 - point.x),
+        };
+        if !(0..width).contains(&x) || !(0..height).contains(&y) {
+            return None;
+        }
+        let index = (y * width + x) as usize;
+        Some((index / 8, 0x80 >> (index % 8)))
+    }
+}
+
+impl OriginDimensions for Gray4Buffer {
+    fn size(&self) -> Size {
+        match self.rotation {
+            DisplayRotation::Rotate0 | DisplayRotation::Rotate180 => Size::new(WIDTH, HEIGHT),
+            DisplayRotation::Rotate90 | DisplayRotation::Rotate270 => Size::new(HEIGHT, WIDTH),
+        }
+    }
+}
+
+impl DrawTarget for Gray4Buffer {
+    type Color = Gray2;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let Some((byte, mask)) = self.position(point) else {
+                continue;
+            };
+            for (plane, bit) in [(&mut self.high, 0b10), (&mut self.low, 0b01)] {
+                match color.luma() & bit != 0 {
+                    true => plane[byte] |= mask,
+                    false => plane[byte] &= !mask,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Draws [`Gray4Buffer`]s on the panel, alongside the epd-waveshare driver.
+///
+/// This needs its own handle on the data/command and busy pins, so it
+/// must only be used between the driver's operations. The driver loads
+/// its own LUT on the next [`epd_waveshare::prelude::WaveshareDisplay::set_lut`],
+/// so switch back with that before drawing in black and white again.
+pub struct GrayscalePanel {
+    dc: Output<'static, ErasedPin>,
+    busy: BusyPin<Input<'static, ErasedPin>>,
+}
+
+impl GrayscalePanel {
+    /// Take a second handle on the display pins.
+    ///
+    /// # Safety
+    ///
+    /// The pins are shared with the epd-waveshare driver, so the caller
+    /// has to make sure the two are never driving the panel at once.
+    pub unsafe fn new(dc: &mut GpioPin<34>, busy: &mut GpioPin<36>, config: DisplayConfig) -> Self {
+        Self {
+            dc: Output::new(dc.clone_unchecked(), Level::Low),
+            busy: BusyPin::new(
+                Input::new(busy.clone_unchecked(), Pull::Up),
+                config.busy_timeout,
+            ),
+        }
+    }
+
+    fn command<SPI: embedded_hal::spi::SpiDevice>(
+        &mut self,
+        spi: &mut SPI,
+        command: u8,
+        data: &[u8],
+    ) -> Result<(), SPI::Error> {
+        self.dc.set_low();
+        spi.write(&[command])?;
+        if !data.is_empty() {
+            self.dc.set_high();
+            spi.write(data)?;
+        }
+        Ok(())
+    }
+
+    /// Point the RAM address counters back at the start of the frame.
+    fn reset_ram_counters<SPI: embedded_hal::spi::SpiDevice>(
+        &mut self,
+        spi: &mut SPI,
+    ) -> Result<(), SPI::Error> {
+        self.command(spi, command::RAM_X_COUNTER, &[0])?;
+        self.command(spi, command::RAM_Y_COUNTER, &[0, 0])
+    }
+
+    fn wait_until_idle<DELAY: embedded_hal::delay::DelayNs>(&mut self, delay: &mut DELAY) {
+        use embedded_hal::digital::InputPin;
+
+        // a timed out busy pin reads as an error, which counts as idle
+        while self.busy.is_high().unwrap_or(false) {
+            delay.delay_us(BUSY_POLL_US);
+        }
+    }
+
+    /// Upload `buffer` and refresh the whole panel with the 4 gray LUT.
+    pub fn draw<SPI, DELAY>(
+        &mut self,
+        spi: &mut SPI,
+        delay: &mut DELAY,
+        buffer: &Gray4Buffer,
+    ) -> Result<(), SPI::Error>
+    where
+        SPI: embedded_hal::spi::SpiDevice,
+        DELAY: embedded_hal::delay::DelayNs,
+    {
+        self.wait_until_idle(delay);
+
+        // x and y increment, over the whole frame
+        let [y_end_low, y_end_high] = ((HEIGHT - 1) as u16).to_le_bytes();
+        self.command(spi, command::DATA_ENTRY_MODE, &[0x03])?;
+        self.command(spi, command::RAM_X_RANGE, &[0, (WIDTH / 8 - 1) as u8])?;
+        self.command(spi, command::RAM_Y_RANGE, &[0, 0, y_end_low, y_end_high])?;
+
+        self.reset_ram_counters(spi)?;
+        self.command(spi, command::WRITE_RAM_BW, &buffer.high)?;
+        self.reset_ram_counters(spi)?;
+        self.command(spi, command::WRITE_RAM_RED, &buffer.low)?;
+
+        self.command(spi, command::WRITE_LUT, &LUT_GRAY4)?;
+        self.command(spi, command::END_OPTION, &[LUT_EOPT])?;
+        self.command(spi, command::GATE_VOLTAGE, &[LUT_VGH])?;
+        self.command(spi, command::SOURCE_VOLTAGE, &LUT_VSH)?;
+        self.command(spi, command::WRITE_VCOM, &[LUT_VCOM])?;
+
+        self.command(
+            spi,
+            command::DISPLAY_UPDATE_CONTROL_2,
+            &[UPDATE_WITH_LUT_REGISTER],
+        )?;
+        self.command(spi, command::MASTER_ACTIVATION, &[])?;
+        self.wait_until_idle(delay);
+        Ok(())
+    }
+}
@@ -3,49 +3,264 @@
 #![feature(type_alias_impl_trait)]
 #![feature(impl_trait_in_assoc_type)]
 
+use core::cell::Cell;
+
 use defmt::write;
-use esp_hal::{peripherals::LPWR, reset::SleepSource};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use esp_hal::{
+    peripherals::LPWR,
+    reset::SleepSource,
+    rtc_cntl::{
+        sleep::{RtcSleepConfig, WakeSource, WakeTriggers},
+        Rtc,
+    },
+};
 
+pub mod accel;
+pub mod alarm;
 mod battery;
+pub mod buttons;
+pub mod clock;
+mod display;
 mod dns;
 mod fonts;
+mod grayscale;
+mod pcf8563;
+pub mod power;
+pub mod self_test;
 pub mod sticky_signal;
+pub mod stopwatch;
+mod storage;
 mod throttle;
 mod time;
 mod ui;
+pub mod vibration;
+#[cfg(feature = "wifi")]
 mod wifi;
 
-pub use battery::{BatteryStatus, BatteryStatusDriver};
-pub use time::GlobalTime;
-pub use ui::drive_display;
-pub use wifi::{get_time, get_weather, wifi};
+pub use battery::{
+    attenuation_max_mv, battery_monitor, check_attenuation, check_attenuation_usable,
+    check_attenuation_with_divider, curve_percentage, curve_voltage, max_battery_mv,
+    percentage_to_voltage, scale_voltage, voltage_to_percentage, AttenuationError, BatteryError,
+    BatteryStatus, BatteryStatusDriver, ChargeDetector, ChargeState, DischargeEstimator,
+    VoltageDivider, BATTERY_PERCENTAGE, BATTERY_STATUS, CHARGE_STATE, CHARGING, CHARGING_OFF_MV,
+    CHARGING_ON_MV, DEFAULT_ATTENUATION, FULL_CHARGE_MV, LIPO_DISCHARGE_CURVE,
+};
+pub use display::{
+    Panel, PanelBuffer, Watchy1in54, Watchy1in54V2, Watchy2in9V2, WatchyDisplay, WatchyV3Display,
+};
+pub use dns::{DnsError, StaticDns};
+pub use pcf8563::{Pcf8563, RtcError};
+pub use storage::{
+    load_wifi_credentials, save_wifi_credentials, Credentials, CREDENTIALS_RECORD_LEN,
+};
+#[cfg(feature = "wifi")]
+pub use time::{
+    bind_ntp_socket, get_default_time, get_time_host, get_time_with_retry, sync_time_with_retry,
+    time_resync_task, NtpSocketBuffers, DEFAULT_NTP_HOST, DEFAULT_NTP_SERVERS,
+    DEFAULT_RESYNC_INTERVAL,
+};
+pub use time::{
+    drive_minute_tick, uptime, DstRule, GlobalTime, NtpSync, SecondaryTimezone, Timezone,
+    TransitionRule, Week, DEFAULT_TIMEZONE, MINUTE_TICK,
+};
+pub use ui::{
+    clear_ghosting, drive_display, epd_bus, refresh_metrics, request_clear_ghosting,
+    request_redraw, setup_epd, show_message, DisplayConfig, Epd, EpdBus, EpdSpi, FaceConfig,
+    RefreshMetrics, RefreshTimings, EPD_INIT_ATTEMPTS,
+};
+#[cfg(feature = "wifi")]
+pub use wifi::{
+    get_time, hold_network, http_get, rssi, scan, shutdown, signal_bars, wait_network_idle, wifi,
+    wifi_available, AccessPoint, HttpError, StaticIpError, WifiConfig, WifiStack, WIFI_RSSI,
+};
+
+/// Play a pattern on the vibration motor, cutting short any that is
+/// already playing. See [`vibration::play`].
+pub static VIBRATION_SIGNAL: Signal<CriticalSectionRawMutex, vibration::Vibration> = Signal::new();
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
 pub enum Button {
+    /// Menu, on GPIO7.
     BottomLeft,
+    /// Back, on GPIO6.
     TopLeft,
+    /// Up, on GPIO0.
     TopRight,
+    /// Down, on GPIO8.
     BottomRight,
 }
 
-// TODO set these channels
-const RTCIO_GPIO4_CHANNEL: u32 = 1 << 10;
-const RTCIO_GPIO25_CHANNEL: u32 = 1 << 6;
-const RTCIO_GPIO26_CHANNEL: u32 = 1 << 7;
-const RTCIO_GPIO35_CHANNEL: u32 = 1 << 5;
+impl Button {
+    /// Every button, in index order.
+    pub const ALL: [Button; 4] = [
+        Button::BottomLeft,
+        Button::TopLeft,
+        Button::TopRight,
+        Button::BottomRight,
+    ];
+}
+
+// The ext1 wakeup status has a bit per RTC IO channel. On the ESP32-S3
+// GPIO0 to GPIO21 are RTC_GPIO0 to RTC_GPIO21, so the channel is just the
+// pin number (see the "RTC IO MUX pin list" in the technical reference
+// manual). This differs from the original ESP32, where the old watchy's
+// GPIO4/25/26/35 were RTC_GPIO10/6/7/5.
+pub const RTCIO_GPIO0_CHANNEL: u32 = 1 << 0;
+pub const RTCIO_GPIO6_CHANNEL: u32 = 1 << 6;
+pub const RTCIO_GPIO7_CHANNEL: u32 = 1 << 7;
+pub const RTCIO_GPIO8_CHANNEL: u32 = 1 << 8;
+
+/// The button channels, indexed by [`Button`].
+pub const BUTTON_CHANNELS: [u32; 4] = [
+    RTCIO_GPIO7_CHANNEL,
+    RTCIO_GPIO6_CHANNEL,
+    RTCIO_GPIO0_CHANNEL,
+    RTCIO_GPIO8_CHANNEL,
+];
+
+// each button needs its own bit for the decode to tell them apart
+const _: () = {
+    let mut i = 0;
+    while i < BUTTON_CHANNELS.len() {
+        assert!(BUTTON_CHANNELS[i].is_power_of_two());
+        let mut j = i + 1;
+        while j < BUTTON_CHANNELS.len() {
+            assert!(BUTTON_CHANNELS[i] != BUTTON_CHANNELS[j]);
+            j += 1;
+        }
+        i += 1;
+    }
+};
+
+/// The ext1 mask for a set of buttons.
+pub fn button_wakeup_mask(buttons: &[Button]) -> u32 {
+    buttons
+        .iter()
+        .fold(0, |mask, button| mask | BUTTON_CHANNELS[*button as usize])
+}
+
+/// Set up ext1 to wake on a press of any of `buttons`, and nothing else,
+/// returning the mask that [`decode_ext1_button`] will see.
+///
+/// The buttons have external pull ups and read low when pressed. The
+/// ESP32-S3 can wake on any pin going low, unlike the original ESP32 which
+/// could only wake when all of them were low.
+///
+/// The sleep functions overwrite the enabled wake sources, so pass a
+/// [`ButtonWakeupSource`] when sleeping rather than calling this directly.
+pub fn configure_button_wakeup(rtc_cntl: &LPWR, buttons: &[Button]) -> u32 {
+    let mask = button_wakeup_mask(buttons);
+
+    // route the pads to the rtc so they are watched while the digital
+    // domain is powered down
+    // SAFETY: the rtc io registers for the button pads aren't touched elsewhere
+    let rtc_io = unsafe { &*esp_hal::peripherals::RTC_IO::PTR };
+    for button in buttons {
+        let channel = BUTTON_CHANNELS[*button as usize].trailing_zeros() as usize;
+        rtc_io
+            .touch_pad(channel)
+            .modify(|_, w| w.mux_sel().set_bit().fun_ie().set_bit().slp_ie().set_bit());
+    }
+
+    rtc_cntl
+        .ext_wakeup1()
+        .modify(|_, w| w.status_clr().set_bit());
+    rtc_cntl
+        .ext_wakeup1()
+        .modify(|_, w| unsafe { w.sel().bits(mask) });
+    // low level, which is any pin low on the s3
+    rtc_cntl
+        .ext_wakeup_conf()
+        .modify(|_, w| w.ext_wakeup1_lv().clear_bit());
+
+    mask
+}
+
+/// Wake from sleep when one of the buttons is pressed, see
+/// [`configure_button_wakeup`].
+pub struct ButtonWakeupSource<'a> {
+    buttons: &'a [Button],
+}
+
+impl<'a> ButtonWakeupSource<'a> {
+    pub fn new(buttons: &'a [Button]) -> Self {
+        Self { buttons }
+    }
+}
+
+impl WakeSource for ButtonWakeupSource<'_> {
+    fn apply(
+        &self,
+        _rtc: &Rtc<'_>,
+        triggers: &mut WakeTriggers,
+        sleep_config: &mut RtcSleepConfig,
+    ) {
+        // the rtc peripherals watch the pads, so keep them powered
+        sleep_config.set_rtc_peri_pd_en(false);
+        triggers.set_ext1(true);
+
+        // SAFETY: only the ext1 wakeup registers are touched, which the rtc driver leaves alone
+        configure_button_wakeup(unsafe { &*LPWR::PTR }, self.buttons);
+    }
+}
+
+/// The channel of the PCF8563 interrupt.
+pub const RTCIO_GPIO5_CHANNEL: u32 = 1 << 5;
+
+/// Wake from sleep when the PCF8563 raises its interrupt, which it does
+/// for the alarm. This is reported as [`WakeupCause::ExternalRtcAlarm`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RtcAlarmWakeupSource;
+
+impl WakeSource for RtcAlarmWakeupSource {
+    fn apply(
+        &self,
+        _rtc: &Rtc<'_>,
+        triggers: &mut WakeTriggers,
+        sleep_config: &mut RtcSleepConfig,
+    ) {
+        sleep_config.set_rtc_peri_pd_en(false);
+        triggers.set_ext0(true);
+
+        // SAFETY: only the ext0 registers and the interrupt pad are touched
+        let (rtc_cntl, rtc_io) = unsafe { (&*LPWR::PTR, &*esp_hal::peripherals::RTC_IO::PTR) };
+        let channel = RTCIO_GPIO5_CHANNEL.trailing_zeros();
+        rtc_io
+            .touch_pad(channel as usize)
+            .modify(|_, w| w.mux_sel().set_bit().fun_ie().set_bit().slp_ie().set_bit());
+        rtc_io
+            .ext_wakeup0()
+            .modify(|_, w| unsafe { w.sel().bits(channel as u8) });
+        // the interrupt is open drain, and pulled low while active
+        rtc_cntl
+            .ext_wakeup_conf()
+            .modify(|_, w| w.ext_wakeup0_lv().clear_bit());
+    }
+}
 
 fn get_ext1_wakeup_button(rtc_cntl: &LPWR) -> Result<Button, u32> {
     // TODO when esp32_hal lets you read the wakeup status, it'd be nice to use that
     // instead of using unsafe.
     let wakeup_bits = rtc_cntl.ext_wakeup1_status().read().bits();
 
-    match wakeup_bits {
-        RTCIO_GPIO26_CHANNEL => Ok(Button::BottomLeft),
-        RTCIO_GPIO25_CHANNEL => Ok(Button::TopLeft),
-        RTCIO_GPIO35_CHANNEL => Ok(Button::TopRight),
-        RTCIO_GPIO4_CHANNEL => Ok(Button::BottomRight),
-        _ => Err(wakeup_bits),
+    decode_ext1_button(wakeup_bits)
+}
+
+/// Decode the ext1 wakeup status mask into the button that woke us.
+///
+/// Returns the mask if it doesn't match exactly one button.
+pub fn decode_ext1_button(bits: u32) -> Result<Button, u32> {
+    match bits {
+        RTCIO_GPIO7_CHANNEL => Ok(Button::BottomLeft),
+        RTCIO_GPIO6_CHANNEL => Ok(Button::TopLeft),
+        RTCIO_GPIO0_CHANNEL => Ok(Button::TopRight),
+        RTCIO_GPIO8_CHANNEL => Ok(Button::BottomRight),
+        _ => Err(bits),
     }
 }
 
@@ -57,12 +272,6 @@ pub enum WakeupCause {
     ExternalRtcAlarm,
     /// One of the buttons was pressed
     ButtonPress(Button),
-    // Probably shouldn't happen since we only set those pins for waking up
-    // TODO turn into Error
-    UnknownExt1(u32),
-    // Probably shouldn't happen
-    // TODO turn into Error
-    Unknown(SleepSource),
 }
 
 impl defmt::Format for WakeupCause {
@@ -70,23 +279,62 @@ impl defmt::Format for WakeupCause {
         match self {
             WakeupCause::Reset => write!(fmt, "reset"),
             WakeupCause::ExternalRtcAlarm => write!(fmt, "external rtc"),
-            WakeupCause::ButtonPress(_) => write!(fmt, "button press"),
-            WakeupCause::UnknownExt1(_) => write!(fmt, "unknown ext"),
-            WakeupCause::Unknown(_) => write!(fmt, "unknown"),
+            WakeupCause::ButtonPress(button) => write!(fmt, "button press: {}", button),
+        }
+    }
+}
+
+/// A wakeup that we didn't ask for.
+#[derive(Debug, Clone, Copy)]
+pub enum WakeupError {
+    /// Woken by ext1, but the mask doesn't match exactly one button.
+    /// Probably shouldn't happen since we only set those pins for waking up.
+    UnknownExt1(u32),
+    /// Woken by a source we never enable.
+    Unknown(SleepSource),
+}
+
+impl defmt::Format for WakeupError {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            WakeupError::UnknownExt1(mask) => write!(fmt, "unknown ext1 mask {=u32:#x}", mask),
+            WakeupError::Unknown(source) => {
+                write!(fmt, "unknown source {}", defmt::Debug2Format(source))
+            }
         }
     }
 }
 
-pub fn get_wakeup_cause(rtc_cntl: &LPWR) -> WakeupCause {
+pub fn get_wakeup_cause(rtc_cntl: &LPWR) -> Result<WakeupCause, WakeupError> {
     let cause = esp_hal::reset::get_wakeup_cause();
 
     match cause {
-        SleepSource::Ext0 => WakeupCause::ExternalRtcAlarm,
-        SleepSource::Ext1 => match get_ext1_wakeup_button(rtc_cntl) {
-            Ok(button) => WakeupCause::ButtonPress(button),
-            Err(mask) => WakeupCause::UnknownExt1(mask),
-        },
-        SleepSource::Undefined => WakeupCause::Reset,
-        _ => WakeupCause::Unknown(cause),
+        SleepSource::Ext0 => Ok(WakeupCause::ExternalRtcAlarm),
+        SleepSource::Ext1 => get_ext1_wakeup_button(rtc_cntl)
+            .map(WakeupCause::ButtonPress)
+            .map_err(WakeupError::UnknownExt1),
+        SleepSource::Undefined => Ok(WakeupCause::Reset),
+        _ => Err(WakeupError::Unknown(cause)),
     }
 }
+
+/// The reason for this boot, which never changes once read.
+static WAKEUP_CAUSE: Mutex<
+    CriticalSectionRawMutex,
+    Cell<Option<Result<WakeupCause, WakeupError>>>,
+> = Mutex::new(Cell::new(None));
+
+/// Read the wakeup cause and cache it for the rest of this boot.
+///
+/// Call this once at startup, so the `LPWR` peripheral can be handed
+/// off afterwards and the cause read with [`wakeup_cause`].
+pub fn init_wakeup_cause(rtc_cntl: &LPWR) -> Result<WakeupCause, WakeupError> {
+    let cause = get_wakeup_cause(rtc_cntl);
+    WAKEUP_CAUSE.lock(|cell| cell.set(Some(cause)));
+    cause
+}
+
+/// The wakeup cause cached by [`init_wakeup_cause`], if it has been called.
+pub fn wakeup_cause() -> Option<Result<WakeupCause, WakeupError>> {
+    WAKEUP_CAUSE.lock(|cell| cell.get())
+}
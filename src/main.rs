@@ -14,36 +14,124 @@ use esp_hal::prelude::*;
 
 use async_debounce::Debouncer;
 use bma423::{Bma423, FeatureInterruptStatus, InterruptDirection, PowerControlFlag, Uninitialized};
+use core::cell::RefCell;
 use core::future;
-use embassy_executor::Spawner;
-use embassy_futures::select::{Either, Either4};
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
-use embassy_time::{Duration, Timer};
+use embassy_embedded_hal::shared_bus::blocking::i2c::I2cDevice;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_futures::select::{Either, Either3, Either4};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_hal_async::digital::Wait;
 use esp_hal::clock::CpuClock;
 use esp_hal::delay::Delay;
-use esp_hal::gpio::{ErasedPin, GpioPin, Input, Io, Level, Output, Pull};
+use esp_hal::gpio::{GpioPin, Input, Io, Level, Output, Pull};
 use esp_hal::i2c::I2C;
 use esp_hal::interrupt::Priority;
-use esp_hal::peripherals::I2C0;
-use esp_hal::rtc_cntl::Rtc;
+use esp_hal::ledc::{
+    channel::{self, ChannelHW, ChannelIFace},
+    timer::{self, TimerIFace},
+    LSGlobalClkSource, Ledc, LowSpeed,
+};
+use esp_hal::peripherals::{I2C0, LPWR};
+use esp_hal::rtc_cntl::{sleep::TimerWakeupSource, Rtc};
 use esp_hal::timer::timg::TimerGroup;
-use esp_hal::timer::{ErasedTimer, OneShotTimer, PeriodicTimer};
+use esp_hal::timer::{ErasedTimer, OneShotTimer};
 use esp_hal::Blocking;
 use esp_hal_embassy::InterruptExecutor;
 use static_cell::StaticCell;
-use watchy_rs::GlobalTime;
+use watchy_rs::accel::TiltSensitivity;
+use watchy_rs::buttons::{ButtonEvent, ButtonGesture, PressKind};
+use watchy_rs::vibration::Vibration;
+use watchy_rs::{
+    Button, DisplayConfig, FaceConfig, GlobalTime, Pcf8563, WakeupCause, WatchyV3Display,
+    VIBRATION_SIGNAL,
+};
+
+/// The network to join, from the `WIFI_SSID` and `WIFI_PASSWORD`
+/// environment variables at build time.
+#[cfg(feature = "wifi")]
+const WIFI_SSID: &str = match option_env!("WIFI_SSID") {
+    Some(ssid) => ssid,
+    None => "",
+};
+#[cfg(feature = "wifi")]
+const WIFI_PASSWORD: &str = match option_env!("WIFI_PASSWORD") {
+    Some(password) => password,
+    None => "",
+};
 
 static TIMERS: StaticCell<[OneShotTimer<ErasedTimer>; 1]> = StaticCell::new();
-static VIBRATION: StaticCell<Output<ErasedPin>> = StaticCell::new();
+static VIBRATION_TIMER: StaticCell<timer::Timer<'static, LowSpeed>> = StaticCell::new();
 static RTC: StaticCell<Rtc> = StaticCell::new();
+static I2C_BUS: StaticCell<I2cBus> = StaticCell::new();
+
+type I2cBus = Mutex<CriticalSectionRawMutex, RefCell<I2C<'static, I2C0, Blocking>>>;
+type SharedI2c = I2cDevice<'static, CriticalSectionRawMutex, I2C<'static, I2C0, Blocking>>;
+
+/// Errors that stop the watch from starting.
+#[derive(Debug)]
+enum AppError {
+    /// A task could not be spawned, because its pool is full.
+    Spawn(SpawnError),
+    /// The accelerometer could not be set up.
+    Accel,
+    /// The pwm for the vibration motor could not be set up.
+    Vibration,
+}
+
+impl From<SpawnError> for AppError {
+    fn from(e: SpawnError) -> Self {
+        AppError::Spawn(e)
+    }
+}
+
+impl defmt::Format for AppError {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            AppError::Spawn(_) => defmt::write!(fmt, "failed to spawn task"),
+            AppError::Accel => defmt::write!(fmt, "failed to set up accelerometer"),
+            AppError::Vibration => defmt::write!(fmt, "failed to set up vibration motor"),
+        }
+    }
+}
+
+/// Hold this button while resetting, or wake with it, to enter recovery mode.
+const RECOVERY_BUTTON: Button = Button::TopLeft;
+
+/// How often to sample the battery.
+const BATTERY_INTERVAL: Duration = Duration::from_secs(60);
 
-/// Run the OS
+/// How often to read the accelerometer temperature. It changes slowly, and
+/// is only accurate to a few degrees anyway.
+const TEMPERATURE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long to sleep for after failing to start, before trying again.
+const SAFE_STATE_SLEEP: core::time::Duration = core::time::Duration::from_secs(60 * 60);
+
+/// Run the OS, sleeping if it fails to start.
+#[main]
+async fn main(low_prio_spawner: Spawner) {
+    if let Err(e) = run(low_prio_spawner).await {
+        defmt::error!("failed to start: {}", e);
+        enter_safe_state();
+    }
+}
+
+/// Deep sleep for a while rather than crashing, since a reboot loop would
+/// drain the battery. The watch starts from scratch when it wakes.
+fn enter_safe_state() -> ! {
+    defmt::info!("entering safe state");
+    // SAFETY: this never returns, so nothing else uses the rtc afterwards
+    let mut rtc = Rtc::new(unsafe { LPWR::steal() });
+    let timer = TimerWakeupSource::new(SAFE_STATE_SLEEP);
+    rtc.sleep_deep(&[&timer]);
+}
+
+/// Set up the hardware and spawn the tasks.
 ///
 /// We have two task spawners, a low priority one and a high prio one which responds to
 /// things like buttons.
-#[main]
-async fn main(low_prio_spawner: Spawner) {
+async fn run(low_prio_spawner: Spawner) -> Result<(), AppError> {
     let peripherals = {
         let mut config = esp_hal::Config::default();
         config.cpu_clock = CpuClock::Clock80MHz;
@@ -53,13 +141,17 @@ async fn main(low_prio_spawner: Spawner) {
     // needed for wifi
     esp_alloc::heap_allocator!(72 * 1024);
 
-    let cause = watchy_rs::get_wakeup_cause(&peripherals.LPWR);
+    // an unexpected wakeup is treated as a reset, so we start from scratch
+    let cause = watchy_rs::init_wakeup_cause(&peripherals.LPWR).unwrap_or_else(|e| {
+        defmt::warn!("unexpected wakeup: {}", e);
+        WakeupCause::Reset
+    });
     defmt::info!("starting due to {:?}", cause);
 
     let rtc = RTC.init(Rtc::new(peripherals.LPWR));
 
     let delay = Delay::new();
-    let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
+    let mut io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
 
     let embassy_timers = {
         let timg0 = TimerGroup::new(peripherals.TIMG0);
@@ -70,17 +162,160 @@ async fn main(low_prio_spawner: Spawner) {
 
     esp_hal_embassy::init(embassy_timers);
 
+    #[cfg(feature = "wifi")]
+    {
+        let wifi_timer = {
+            let timg1 = TimerGroup::new(peripherals.TIMG1);
+            let timer0: ErasedTimer = timg1.timer0.into();
+            esp_hal::timer::PeriodicTimer::new(timer0)
+        };
+
+        low_prio_spawner.spawn(watchy_rs::wifi(
+            WIFI_SSID,
+            WIFI_PASSWORD,
+            watchy_rs::WifiConfig::default(),
+            wifi_timer,
+            peripherals.RNG,
+            peripherals.RADIO_CLK,
+            peripherals.WIFI,
+            low_prio_spawner,
+        ))?;
+    }
+
+    // the buttons held down while booting, indexed by button
+    let held = [
+        Input::new(&mut io.pins.gpio7, Pull::None).is_low(),
+        Input::new(&mut io.pins.gpio6, Pull::None).is_low(),
+        Input::new(&mut io.pins.gpio0, Pull::None).is_low(),
+        Input::new(&mut io.pins.gpio8, Pull::None).is_low(),
+    ];
+
+    // hold both bottom buttons while resetting to run the self test
+    let self_test_requested = matches!(cause, WakeupCause::Reset)
+        && held[Button::BottomLeft as usize]
+        && held[Button::BottomRight as usize];
+
+    if self_test_requested {
+        defmt::info!("running self test");
+
+        let bus = watchy_rs::epd_bus(
+            peripherals.SPI2,
+            io.pins.gpio47,
+            io.pins.gpio46,
+            io.pins.gpio48,
+        );
+        let display = WatchyV3Display::setup(
+            &bus,
+            io.pins.gpio33,
+            io.pins.gpio34,
+            io.pins.gpio35,
+            io.pins.gpio36,
+            delay,
+            DisplayConfig::default(),
+        );
+
+        let mut motor = Output::new(io.pins.gpio17, Level::Low);
+        let mut battery = watchy_rs::BatteryStatusDriver::new_watchy(
+            io.pins.gpio9,
+            io.pins.gpio10,
+            peripherals.ADC1,
+        )
+        .inspect_err(|e| defmt::error!("self test: can't measure the battery: {}", e))
+        .ok();
+        let mut i2c = I2C::new(peripherals.I2C0, io.pins.gpio12, io.pins.gpio11, 400.kHz());
+
+        match display {
+            Ok(mut display) => {
+                let (epd, spi, delay) = display.parts();
+                let report = watchy_rs::self_test::self_test(
+                    epd,
+                    spi,
+                    delay,
+                    &mut motor,
+                    battery.as_mut(),
+                    &mut i2c,
+                )
+                .await;
+                defmt::info!("self test passed: {}, {}", report.passed(), report);
+            }
+            Err(_) => defmt::error!("self test: failed to initialise the display"),
+        }
+
+        // stay here until reset, so the results can be read off
+        return Ok(());
+    }
+
+    let recovery_requested = match cause {
+        WakeupCause::ButtonPress(button) => button == RECOVERY_BUTTON,
+        WakeupCause::Reset => held[RECOVERY_BUTTON as usize],
+        _ => false,
+    };
+
+    if recovery_requested {
+        defmt::info!("entering recovery mode");
+        #[cfg(feature = "wifi")]
+        watchy_rs::hold_network(true);
+
+        let bus = watchy_rs::epd_bus(
+            peripherals.SPI2,
+            io.pins.gpio47,
+            io.pins.gpio46,
+            io.pins.gpio48,
+        );
+        let shown = WatchyV3Display::setup(
+            &bus,
+            io.pins.gpio33,
+            io.pins.gpio34,
+            io.pins.gpio35,
+            io.pins.gpio36,
+            delay,
+            DisplayConfig::default(),
+        )
+        .and_then(|mut display| display.show_message("recovery mode"));
+        if shown.is_err() {
+            defmt::error!("failed to show recovery mode on the display");
+        }
+
+        // stay awake until reset, so the watch can be reflashed
+        return Ok(());
+    }
+
     {
         defmt::info!("starting button / vibro handler");
+        watchy_rs::buttons::on_press(Button::TopRight, |_| watchy_rs::stopwatch::toggle());
+        watchy_rs::buttons::on_press(Button::BottomRight, |_| watchy_rs::stopwatch::reset());
+
         static EXECUTOR: StaticCell<InterruptExecutor<2>> = StaticCell::new();
 
         let sw_ints = SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
         let executor = InterruptExecutor::new(sw_ints.software_interrupt2);
         let executor = EXECUTOR.init(executor);
         let spawner = executor.start(Priority::Priority3);
-        let vibration_motor = Output::new(io.pins.gpio17, Level::Low);
-        let vibration_motor = VIBRATION.init(vibration_motor);
-        spawner.must_spawn(handle_buttons(
+        let vibration_motor = {
+            let mut ledc = Ledc::new(peripherals.LEDC);
+            ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+
+            let mut timer = ledc.get_timer::<LowSpeed>(timer::Number::Timer0);
+            timer
+                .configure(timer::config::Config {
+                    duty: timer::config::Duty::Duty8Bit,
+                    clock_source: timer::LSClockSource::APBClk,
+                    frequency: VIBRATION_PWM_KHZ.kHz(),
+                })
+                .map_err(|_| AppError::Vibration)?;
+            let timer: &'static _ = VIBRATION_TIMER.init(timer);
+
+            let mut motor = ledc.get_channel(channel::Number::Channel0, io.pins.gpio17);
+            motor
+                .configure(channel::config::Config {
+                    timer,
+                    duty_pct: 0,
+                    pin_config: channel::config::PinConfig::PushPull,
+                })
+                .map_err(|_| AppError::Vibration)?;
+            motor
+        };
+        spawner.spawn(handle_buttons(
             io.pins.gpio7,
             io.pins.gpio6,
             io.pins.gpio0,
@@ -88,28 +323,29 @@ async fn main(low_prio_spawner: Spawner) {
             io.pins.gpio14,
             io.pins.gpio13,
             vibration_motor,
-        ));
-    }
-
-    {
-        let wifi_timer = {
-            let timg1 = TimerGroup::new(peripherals.TIMG1);
-            let timer0: ErasedTimer = timg1.timer0.into();
-            PeriodicTimer::new(timer0)
-        };
-
-        low_prio_spawner.must_spawn(watchy_rs::wifi(
-            wifi_timer,
-            peripherals.RNG,
-            peripherals.RADIO_CLK,
-            peripherals.WIFI,
-            low_prio_spawner,
-        ));
+            DebounceConfig::default(),
+            watchy_rs::buttons::DEFAULT_LONG_PRESS,
+        ))?;
     }
 
     let global_time = GlobalTime::new(rtc);
 
-    low_prio_spawner.must_spawn(watchy_rs::drive_display(
+    low_prio_spawner.spawn(watchy_rs::drive_minute_tick(global_time))?;
+    #[cfg(feature = "wifi")]
+    low_prio_spawner.spawn(watchy_rs::time_resync_task(
+        global_time,
+        watchy_rs::DEFAULT_RESYNC_INTERVAL,
+    ))?;
+    low_prio_spawner.spawn(watchy_rs::stopwatch::drive_stopwatch())?;
+    low_prio_spawner.spawn(watchy_rs::vibration::notify_charging())?;
+    low_prio_spawner.spawn(watchy_rs::battery_monitor(
+        io.pins.gpio9,
+        io.pins.gpio10,
+        peripherals.ADC1,
+        BATTERY_INTERVAL,
+    ))?;
+
+    low_prio_spawner.spawn(watchy_rs::drive_display(
         peripherals.SPI2,
         io.pins.gpio47,
         io.pins.gpio46,
@@ -120,61 +356,122 @@ async fn main(low_prio_spawner: Spawner) {
         io.pins.gpio36,
         global_time,
         delay,
-        io.pins.gpio9,
-        io.pins.gpio10,
-        peripherals.ADC1,
-    ));
-
-    // {
-    //     let i2c = I2C_G.init(peripherals.I2C0);
-    //     let i2c0 = I2C::new(i2c, io.pins.gpio12, io.pins.gpio11, 400.kHz(), clocks, None);
-    //     let accel = Bma423::new(
-    //         i2c0,
-    //         bma423::Config {
-    //             bandwidth: bma423::AccelConfigBandwidth::CicAvg8,
-    //             range: bma423::AccelRange::Range2g,
-    //             performance_mode: bma423::AccelConfigPerfMode::CicAvg,
-    //             sample_rate: bma423::AccelConfigOdr::Odr100,
-    //         },
-    //     );
-    //     low_prio_spawner.must_spawn(handle_accel(accel, delay));
-    // }
-
-    let time = watchy_rs::get_time().await;
-    if let Some(time) = time {
-        if time.offset < 0 {
-            panic!("invalid response");
+        FaceConfig::default(),
+        DisplayConfig::default(),
+    ))?;
+
+    // the i2c bus is shared between the accelerometer and the external rtc
+    let i2c_bus = I2C_BUS.init(Mutex::new(RefCell::new(I2C::new(
+        peripherals.I2C0,
+        io.pins.gpio12,
+        io.pins.gpio11,
+        400.kHz(),
+    ))));
+
+    let mut ext_rtc = Pcf8563::new(I2cDevice::new(i2c_bus));
+
+    // the offset survives deep sleep, but not a reset
+    if global_time.time_since_sync().is_none() {
+        if let Err(e) = global_time.init_from_rtc(&mut ext_rtc) {
+            defmt::warn!("couldn't read the time from the rtc: {:?}", e);
         }
-        global_time.init_offset(time.offset as u64);
-        global_time.init_time(time.seconds, time.seconds_fraction);
+    }
+
+    if let WakeupCause::ExternalRtcAlarm = cause {
+        if let Err(e) = watchy_rs::alarm::ring(&mut ext_rtc).await {
+            defmt::error!("failed to ring alarm: {:?}", e);
+        }
+        low_prio_spawner.spawn(watchy_rs::alarm::dismiss_alarm_task())?;
+    }
+
+    {
+        let accel = Bma423::new(
+            I2cDevice::new(i2c_bus),
+            bma423::Config {
+                bandwidth: bma423::AccelConfigBandwidth::CicAvg8,
+                range: bma423::AccelRange::Range2g,
+                performance_mode: bma423::AccelConfigPerfMode::CicAvg,
+                sample_rate: bma423::AccelConfigOdr::Odr100,
+            },
+        );
+        low_prio_spawner.spawn(handle_accel(
+            accel,
+            I2cDevice::new(i2c_bus),
+            delay,
+            global_time,
+            TiltSensitivity::default(),
+        ))?;
+    }
+
+    #[cfg(feature = "wifi")]
+    sync_time(global_time, cause, &mut ext_rtc).await;
+
+    Ok(())
+}
+
+/// Sync the time after a reset, or every wake if the power profile asks for it.
+#[cfg(feature = "wifi")]
+async fn sync_time(global_time: GlobalTime, cause: WakeupCause, ext_rtc: &mut Pcf8563<SharedI2c>) {
+    if !matches!(cause, WakeupCause::Reset) && !watchy_rs::power::power_profile().sync_on_wake() {
+        defmt::info!("skipping time sync");
+        return;
+    }
+
+    if let Some(time) = watchy_rs::get_time().await {
+        global_time.sync(&time);
         defmt::info!("seconds: {}", time.offset);
+
+        if let Err(e) = global_time.persist_to_rtc(ext_rtc) {
+            defmt::error!("failed to write the time to the rtc: {:?}", e);
+        }
     } else {
         defmt::info!("couldn't get time");
     }
+
+    // the network task turns the radio off once it runs out of requests
+    watchy_rs::wait_network_idle().await;
+    defmt::info!("network work done");
 }
 
 #[embassy_executor::task]
 async fn handle_accel(
-    accel: Bma423<I2C<'static, I2C0, Blocking>, Uninitialized>,
-    mut delay: Delay,
+    accel: Bma423<SharedI2c, Uninitialized>,
+    i2c: SharedI2c,
+    delay: Delay,
+    global_time: GlobalTime,
+    tilt_sensitivity: TiltSensitivity,
 ) {
-    let mut accel = accel.init(&mut delay).unwrap();
+    if let Err(e) = run_accel(accel, i2c, delay, global_time, tilt_sensitivity).await {
+        defmt::error!("stopping accelerometer: {}", e);
+    }
+}
+
+async fn run_accel(
+    accel: Bma423<SharedI2c, Uninitialized>,
+    mut i2c: SharedI2c,
+    mut delay: Delay,
+    global_time: GlobalTime,
+    tilt_sensitivity: TiltSensitivity,
+) -> Result<(), AppError> {
+    let mut accel = accel.init(&mut delay).map_err(|_| AppError::Accel)?;
     accel
         .set_power_control(PowerControlFlag::Auxiliary)
-        .unwrap();
+        .map_err(|_| AppError::Accel)?;
 
     accel
         .set_interrupt_config(
             bma423::InterruptLine::Line1,
             InterruptDirection::Input(bma423::InterruptTriggerCondition::Edge),
         )
-        .unwrap();
+        .map_err(|_| AppError::Accel)?;
 
-    let mut features = accel.edit_features().unwrap();
+    let mut features = accel.edit_features().map_err(|_| AppError::Accel)?;
     features
         .set_tap_config(bma423::features::TapFeature::SingleTap, 3, true)
-        .unwrap();
-    features.write().unwrap();
+        .map_err(|_| AppError::Accel)?;
+    features.write().map_err(|_| AppError::Accel)?;
+    watchy_rs::accel::enable_step_counter(&mut i2c).map_err(|_| AppError::Accel)?;
+    watchy_rs::accel::enable_wrist_tilt(&mut i2c).map_err(|_| AppError::Accel)?;
 
     accel
         .map_feature_interrupt(
@@ -182,18 +479,193 @@ async fn handle_accel(
             FeatureInterruptStatus::SingleTap,
             true,
         )
-        .unwrap();
+        .map_err(|_| AppError::Accel)?;
 
+    let mut daily_steps = watchy_rs::accel::DailySteps::load();
+    let mut last_temperature: Option<Instant> = None;
+    let mut orientation = watchy_rs::accel::OrientationTracker::new();
     loop {
-        // -z is face up
-        // +x is vertical
-        // +y is rotated left
-        let (x, y, z) = accel.accel_norm_int().unwrap();
-        defmt::info!("ACCEL: x: {} y: {} z: {}", x, y, z);
-        Timer::after(Duration::from_millis(1000 * 60 * 60)).await;
+        match watchy_rs::accel::read_steps(&mut i2c) {
+            Ok(total) => {
+                let steps = daily_steps.update(total, global_time.now().date());
+                daily_steps.persist();
+                if watchy_rs::accel::DAILY_STEPS.signal_if_changed(steps) {
+                    defmt::info!("STEPS: {}", steps);
+                }
+            }
+            Err(e) => defmt::warn!("failed to read steps: {:?}", e),
+        }
+
+        match watchy_rs::accel::read_accel_raw(&mut i2c) {
+            Ok(sample) => {
+                let orientation = orientation.update(sample);
+                if watchy_rs::accel::ORIENTATION.signal_if_changed(orientation) {
+                    defmt::info!("ORIENTATION: {}", orientation);
+                    watchy_rs::request_redraw();
+                }
+            }
+            Err(e) => defmt::warn!("failed to read accelerometer: {:?}", e),
+        }
+
+        if !last_temperature.is_some_and(|at| at.elapsed() < TEMPERATURE_INTERVAL) {
+            last_temperature = Some(Instant::now());
+
+            // -z is face up
+            // +x is vertical
+            // +y is rotated left
+            match accel.accel_norm_int() {
+                Ok((x, y, z)) => defmt::info!("ACCEL: x: {} y: {} z: {}", x, y, z),
+                Err(_) => defmt::warn!("failed to read accelerometer"),
+            }
+            match watchy_rs::accel::read_temperature(&mut i2c) {
+                Ok(Some(temperature)) => {
+                    defmt::info!("TEMP: {}C", temperature);
+                    watchy_rs::accel::ACCEL_TEMPERATURE.signal(temperature);
+                }
+                Ok(None) => defmt::debug!("temperature not ready"),
+                Err(e) => defmt::warn!("failed to read temperature: {:?}", e),
+            }
+        }
+
+        // read the steps on each interrupt or minute, but nothing else
+        if let Either::Second(()) = embassy_futures::select::select(
+            watchy_rs::MINUTE_TICK.wait("accel"),
+            watchy_rs::accel::ACCEL_INTERRUPT.wait("accel"),
+        )
+        .await
+        {
+            handle_accel_interrupt(&mut i2c, tilt_sensitivity);
+        }
     }
 }
 
+/// Work out which feature fired, and redraw if the wrist was raised.
+fn handle_accel_interrupt(i2c: &mut SharedI2c, tilt_sensitivity: TiltSensitivity) {
+    let status = match watchy_rs::accel::read_interrupt_status(i2c) {
+        Ok(status) => status,
+        Err(e) => {
+            defmt::warn!("failed to read accelerometer interrupts: {:?}", e);
+            return;
+        }
+    };
+
+    if !status.wrist_tilt() {
+        return;
+    }
+
+    match watchy_rs::accel::read_accel_raw(i2c) {
+        Ok(sample) if tilt_sensitivity.is_raised(sample) => {
+            defmt::info!("wrist raised, redrawing");
+            watchy_rs::request_redraw();
+        }
+        Ok(_) => defmt::debug!("wrist tilted but not raised"),
+        Err(e) => defmt::warn!("failed to read accelerometer: {:?}", e),
+    }
+}
+
+/// The longest the vibration motor may stay on, no matter how often it is signalled.
+const MAX_VIBRATION: Duration = Duration::from_secs(2);
+
+/// The pwm frequency for the vibration motor in kHz, above hearing so it
+/// doesn't whine.
+const VIBRATION_PWM_KHZ: u32 = 25;
+
+/// The feedback for button presses, soft enough not to be a nuisance.
+const BUTTON_TICK: Vibration = Vibration::new(watchy_rs::vibration::TICK)
+    .with_intensity(watchy_rs::vibration::GENTLE_INTENSITY);
+
+/// The vibration motor, driven with pwm so it can buzz softly.
+type VibrationMotor = channel::Channel<'static, LowSpeed, GpioPin<17>>;
+
+/// Debounce times for each kind of input.
+#[derive(Clone, Copy)]
+struct DebounceConfig {
+    /// Mechanical buttons bounce for a while after being pressed.
+    buttons: Duration,
+    /// The accelerometer interrupt line is clean, so needs very little.
+    accel_interrupt: Duration,
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        Self {
+            buttons: Duration::from_millis(20),
+            accel_interrupt: Duration::from_millis(2),
+        }
+    }
+}
+
+/// Drive the vibration motor at `intensity`, from 0 for off to 255.
+fn set_vibration(motor: &VibrationMotor, intensity: u8) {
+    // the timer has 8 bits of duty, so the intensity maps straight across
+    motor.set_duty_hw(u32::from(intensity));
+}
+
+/// Work out what a press of `button`, which has just gone down, turns into.
+///
+/// It's a long press if it's still down once `long_press` passes, a combo
+/// if another button goes down soon after, and a double press if it's
+/// pressed again soon after being released.
+async fn classify_press<B: Wait>(
+    buttons: &mut [B; 4],
+    button: Button,
+    long_press: Duration,
+) -> ButtonGesture {
+    let (before, rest) = buttons.split_at_mut(button as usize);
+    let Some((this, after)) = rest.split_first_mut() else {
+        unreachable!("button index out of range");
+    };
+    // the other buttons, in index order
+    let mut others = before.iter_mut().chain(after.iter_mut());
+    let (Some(a), Some(b), Some(c)) = (others.next(), others.next(), others.next()) else {
+        unreachable!("there are four buttons");
+    };
+    let other = |i: usize| Button::ALL[if i < button as usize { i } else { i + 1 }];
+
+    let combo = async {
+        let pressed = embassy_futures::select::select3(
+            a.wait_for_falling_edge(),
+            b.wait_for_falling_edge(),
+            c.wait_for_falling_edge(),
+        );
+        match embassy_time::with_timeout(watchy_rs::buttons::COMBO_WINDOW, pressed).await {
+            Ok(Either3::First(_)) => other(0),
+            Ok(Either3::Second(_)) => other(1),
+            Ok(Either3::Third(_)) => other(2),
+            Err(_) => future::pending().await,
+        }
+    };
+
+    let kind = match embassy_futures::select::select3(
+        this.wait_for_rising_edge(),
+        Timer::after(long_press),
+        combo,
+    )
+    .await
+    {
+        Either3::First(_) => PressKind::Short,
+        Either3::Second(_) => {
+            VIBRATION_SIGNAL.signal(BUTTON_TICK);
+            PressKind::Long
+        }
+        Either3::Third(second) => return ButtonGesture::Combo(button, second),
+    };
+
+    if kind == PressKind::Short && watchy_rs::buttons::detect_double_press() {
+        let again = embassy_time::with_timeout(
+            watchy_rs::buttons::DOUBLE_PRESS_WINDOW,
+            this.wait_for_falling_edge(),
+        )
+        .await;
+        if again.is_ok() {
+            let _ = this.wait_for_rising_edge().await;
+            return ButtonGesture::DoublePress(button);
+        }
+    }
+
+    ButtonGesture::Press(ButtonEvent { button, kind })
+}
+
 /// Periodically print something.
 #[embassy_executor::task]
 async fn handle_buttons(
@@ -202,37 +674,94 @@ async fn handle_buttons(
     p3: GpioPin<0>,
     p4: GpioPin<8>,
     acc_int_1: GpioPin<14>,
-    _acc_int_2: GpioPin<13>,
-    vibration: &'static mut Output<'static, ErasedPin>,
+    acc_int_2: GpioPin<13>,
+    vibration: VibrationMotor,
+    debounce: DebounceConfig,
+    long_press: Duration,
 ) {
-    let vibration_signal = embassy_sync::signal::Signal::<NoopRawMutex, _>::new();
-
-    let debounce_time = embassy_time::Duration::from_millis(5);
-    let mut button_1 = Debouncer::new(Input::new(p1, Pull::None), debounce_time);
-    let mut button_2 = Debouncer::new(Input::new(p2, Pull::None), debounce_time);
-    let mut button_3 = Debouncer::new(Input::new(p3, Pull::None), debounce_time);
-    let mut button_4 = Debouncer::new(Input::new(p4, Pull::None), debounce_time);
-    let mut interrupt = Debouncer::new(Input::new(acc_int_1, Pull::Up), debounce_time);
+    // indexed by button
+    let mut buttons = [
+        Debouncer::new(Input::new(p1, Pull::None), debounce.buttons),
+        Debouncer::new(Input::new(p2, Pull::None), debounce.buttons),
+        Debouncer::new(Input::new(p3, Pull::None), debounce.buttons),
+        Debouncer::new(Input::new(p4, Pull::None), debounce.buttons),
+    ];
+    let mut interrupt = Debouncer::new(Input::new(acc_int_1, Pull::Up), debounce.accel_interrupt);
+
+    // data ready pulses too quickly to debounce, and only fires while streaming
+    let mut data_ready = Input::new(acc_int_2, Pull::Down);
 
     let drive_accel = async {
         loop {
-            interrupt.wait_for_any_edge().await.unwrap();
-            defmt::info!("TAP")
+            match embassy_futures::select::select(
+                interrupt.wait_for_any_edge(),
+                data_ready.wait_for_rising_edge(),
+            )
+            .await
+            {
+                Either::First(Ok(())) => {
+                    defmt::info!("ACCEL INTERRUPT");
+                    watchy_rs::accel::ACCEL_INTERRUPT.signal(());
+                }
+                Either::Second(()) => watchy_rs::accel::ACCEL_DATA_READY.signal(()),
+                _ => {}
+            }
         }
     };
 
     let drive_vibro = async {
-        let mut vib_timeout = futures::future::Either::Left(future::pending());
+        let mut step_timeout = futures::future::Either::Left(future::pending());
+        // the vibration being played, and the index of the current step
+        let mut playing: Option<(Vibration, usize)> = None;
+        // when the motor was turned on, if it is on
+        let mut on_since: Option<Instant> = None;
+        // ignore requests until this time after the motor has been forced off
+        let mut cooldown_until: Option<Instant> = None;
         loop {
-            match embassy_futures::select::select(vibration_signal.wait(), vib_timeout).await {
-                Either::First(new_wait) => {
-                    vibration.set_high();
-                    vib_timeout =
-                        futures::future::Either::Right(embassy_time::Timer::after_millis(new_wait))
+            // borrowed so an ignored request doesn't drop the running step
+            match embassy_futures::select::select(VIBRATION_SIGNAL.wait(), &mut step_timeout).await
+            {
+                Either::First(_) if cooldown_until.is_some_and(|until| Instant::now() < until) => {
+                    defmt::debug!("vibration motor cooling down, ignoring request");
+                    continue;
                 }
+                // a new pattern replaces the current one from its first step
+                Either::First(vibration) => playing = Some((vibration, 0)),
                 Either::Second(_) => {
-                    vibration.set_low();
-                    vib_timeout = futures::future::Either::Left(future::pending())
+                    if on_since.is_some_and(|started| started.elapsed() >= MAX_VIBRATION) {
+                        defmt::warn!("vibration motor on for too long, forcing it off");
+                        cooldown_until = Some(Instant::now() + MAX_VIBRATION);
+                        playing = None;
+                    }
+                    if let Some((_, step)) = &mut playing {
+                        *step += 1;
+                    }
+                }
+            }
+
+            let step = playing.and_then(|(vibration, step)| {
+                Some((step, vibration.pattern.step(step)?, vibration.intensity))
+            });
+            match step {
+                // steps alternate on and off, starting with on
+                Some((step, millis, intensity)) if step % 2 == 0 => {
+                    set_vibration(&vibration, intensity);
+                    let started = *on_since.get_or_insert_with(Instant::now);
+                    // never stay on longer than the max, even across patterns
+                    let off_at = (Instant::now() + Duration::from_millis(millis))
+                        .min(started + MAX_VIBRATION);
+                    step_timeout = futures::future::Either::Right(Timer::at(off_at))
+                }
+                Some((_, millis, _)) => {
+                    set_vibration(&vibration, 0);
+                    on_since = None;
+                    step_timeout = futures::future::Either::Right(Timer::after_millis(millis))
+                }
+                None => {
+                    set_vibration(&vibration, 0);
+                    on_since = None;
+                    playing = None;
+                    step_timeout = futures::future::Either::Left(future::pending())
                 }
             }
         }
@@ -240,35 +769,34 @@ async fn handle_buttons(
 
     let drive_buttons = async {
         loop {
-            let buttons = embassy_futures::select::select4(
+            let [button_1, button_2, button_3, button_4] = &mut buttons;
+            let pressed = embassy_futures::select::select4(
                 button_1.wait_for_falling_edge(),
                 button_2.wait_for_falling_edge(),
                 button_3.wait_for_falling_edge(),
                 button_4.wait_for_falling_edge(),
             );
 
-            let res = embassy_futures::select::select(buttons, future::pending::<()>()).await;
+            let res = embassy_futures::select::select(pressed, future::pending::<()>()).await;
 
             match res {
                 Either::First(a) => {
-                    vibration_signal.signal(60);
-                    match a {
-                        Either4::First(_) => {
-                            defmt::info!("button 1 pressed");
-                        }
-                        Either4::Second(_) => {
-                            defmt::info!("button 2 pressed");
-                        }
-                        Either4::Third(_) => {
-                            defmt::info!("button 3 pressed");
-                        }
-                        Either4::Fourth(_) => {
-                            defmt::info!("button 4 pressed");
-                        }
-                    }
+                    VIBRATION_SIGNAL.signal(BUTTON_TICK);
+                    let button = match a {
+                        Either4::First(_) => Button::BottomLeft,
+                        Either4::Second(_) => Button::TopLeft,
+                        Either4::Third(_) => Button::TopRight,
+                        Either4::Fourth(_) => Button::BottomRight,
+                    };
+
+                    let gesture = classify_press(&mut buttons, button, long_press).await;
+                    defmt::info!("button gesture {}", gesture);
+                    watchy_rs::request_redraw();
+                    watchy_rs::buttons::publish(gesture);
+                    watchy_rs::buttons::dispatch_gesture(gesture);
                 }
                 Either::Second(_) => {
-                    vibration_signal.signal(60);
+                    VIBRATION_SIGNAL.signal(BUTTON_TICK);
                 }
             }
         }
@@ -0,0 +1,187 @@
+//! A minimal driver for the PCF8563 external RTC.
+//!
+//! The chip sits on the I2C bus at address `0x51`. Registers are BCD encoded.
+//!
+//! The time is kept in seven consecutive registers, which are written in
+//! one transaction so they can't roll over part way through:
+//!
+//! | register | contents                                    |
+//! |----------|---------------------------------------------|
+//! | `0x02`   | VL (bit 7, clock integrity lost), seconds   |
+//! | `0x03`   | minutes                                     |
+//! | `0x04`   | hours, 24 hour                              |
+//! | `0x05`   | day of the month, 1-31                      |
+//! | `0x06`   | weekday, 0-6 from sunday (not BCD)          |
+//! | `0x07`   | century (bit 7, clear for 20xx), month 1-12 |
+//! | `0x08`   | year within the century, 0-99               |
+
+use embedded_hal::i2c::I2c;
+use time::{Date, Month, OffsetDateTime, Time};
+
+const ADDRESS: u8 = 0x51;
+
+const CONTROL_STATUS_2: u8 = 0x01;
+const VL_SECONDS: u8 = 0x02;
+const MINUTE_ALARM: u8 = 0x09;
+
+/// Control status 2: alarm flag, set by the chip when the alarm fires.
+const AF: u8 = 1 << 3;
+/// Control status 2: alarm interrupt enable, drives INT low on alarm.
+const AIE: u8 = 1 << 1;
+/// Months register: set when the year is outside of 20xx.
+const CENTURY: u8 = 1 << 7;
+/// Alarm registers: set to disable matching on that field.
+const ALARM_DISABLE: u8 = 1 << 7;
+
+/// Seconds register: set by the chip when its supply dropped too low to
+/// keep time.
+const VL: u8 = 1 << 7;
+
+/// Reading the time from the RTC failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RtcError<E> {
+    I2c(E),
+    /// The chip lost power at some point, so the time can't be trusted.
+    ClockIntegrityLost,
+    /// The registers don't hold a valid date and time.
+    InvalidTime,
+}
+
+impl<E> From<E> for RtcError<E> {
+    fn from(error: E) -> Self {
+        RtcError::I2c(error)
+    }
+}
+
+pub struct Pcf8563<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C: I2c> Pcf8563<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Self { i2c }
+    }
+
+    /// Set the clock, which should be in UTC. This also clears the VL flag.
+    pub fn set_time(&mut self, time: OffsetDateTime) -> Result<(), I2C::Error> {
+        let year = time.year();
+        let century = match year {
+            2000..=2099 => 0,
+            _ => CENTURY,
+        };
+
+        self.i2c.write(
+            ADDRESS,
+            &[
+                VL_SECONDS,
+                to_bcd(time.second()),
+                to_bcd(time.minute()),
+                to_bcd(time.hour()),
+                to_bcd(time.day()),
+                time.weekday().number_days_from_sunday(),
+                century | to_bcd(u8::from(time.month())),
+                to_bcd(year.rem_euclid(100) as u8),
+            ],
+        )
+    }
+
+    /// Read the clock, as set by [`Pcf8563::set_time`].
+    ///
+    /// Fails if the chip reports that it lost power since it was set.
+    pub fn get_time(&mut self) -> Result<OffsetDateTime, RtcError<I2C::Error>> {
+        let mut registers = [0; 7];
+        self.i2c
+            .write_read(ADDRESS, &[VL_SECONDS], &mut registers)?;
+        let [seconds, minutes, hours, day, _weekday, century_month, year] = registers;
+
+        if seconds & VL != 0 {
+            return Err(RtcError::ClockIntegrityLost);
+        }
+
+        let century = match century_month & CENTURY {
+            0 => 2000,
+            _ => 1900,
+        };
+        let month =
+            Month::try_from(from_bcd(century_month & 0x1f)).map_err(|_| RtcError::InvalidTime)?;
+        let date = Date::from_calendar_date(
+            century + i32::from(from_bcd(year)),
+            month,
+            from_bcd(day & 0x3f),
+        )
+        .map_err(|_| RtcError::InvalidTime)?;
+        let time = Time::from_hms(
+            from_bcd(hours & 0x3f),
+            from_bcd(minutes & 0x7f),
+            from_bcd(seconds & 0x7f),
+        )
+        .map_err(|_| RtcError::InvalidTime)?;
+
+        Ok(date.with_time(time).assume_utc())
+    }
+
+    /// Program the alarm to fire at the given time of day and enable the interrupt.
+    ///
+    /// Day and weekday matching are disabled so the alarm fires every day.
+    pub fn set_alarm(&mut self, hour: u8, minute: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(
+            ADDRESS,
+            &[
+                MINUTE_ALARM,
+                to_bcd(minute),
+                to_bcd(hour),
+                ALARM_DISABLE,
+                ALARM_DISABLE,
+            ],
+        )?;
+        self.update_control_status_2(|status| (status | AIE) & !AF)
+    }
+
+    /// Read back the configured alarm, if it is enabled.
+    pub fn get_alarm(&mut self) -> Result<Option<(u8, u8)>, I2C::Error> {
+        let mut alarm = [0; 2];
+        self.i2c.write_read(ADDRESS, &[MINUTE_ALARM], &mut alarm)?;
+        let [minute, hour] = alarm;
+
+        if minute & ALARM_DISABLE != 0 || hour & ALARM_DISABLE != 0 {
+            return Ok(None);
+        }
+
+        Ok(Some((from_bcd(hour & 0x3f), from_bcd(minute & 0x7f))))
+    }
+
+    /// Disable the alarm and its interrupt.
+    pub fn disable_alarm(&mut self) -> Result<(), I2C::Error> {
+        self.i2c.write(
+            ADDRESS,
+            &[
+                MINUTE_ALARM,
+                ALARM_DISABLE,
+                ALARM_DISABLE,
+                ALARM_DISABLE,
+                ALARM_DISABLE,
+            ],
+        )?;
+        self.update_control_status_2(|status| status & !(AIE | AF))
+    }
+
+    /// Clear the alarm flag, releasing the interrupt line.
+    pub fn clear_alarm_flag(&mut self) -> Result<(), I2C::Error> {
+        self.update_control_status_2(|status| status & !AF)
+    }
+
+    fn update_control_status_2(&mut self, f: impl FnOnce(u8) -> u8) -> Result<(), I2C::Error> {
+        let mut status = [0];
+        self.i2c
+            .write_read(ADDRESS, &[CONTROL_STATUS_2], &mut status)?;
+        self.i2c.write(ADDRESS, &[CONTROL_STATUS_2, f(status[0])])
+    }
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
@@ -0,0 +1,151 @@
+//! Power profiles, trading battery life for responsiveness.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{with_timeout, Duration};
+use esp_hal::rtc_cntl::{sleep::TimerWakeupSource, Rtc};
+
+use crate::sticky_signal::StickySignal;
+use crate::{Button, ButtonWakeupSource, GlobalTime, RtcAlarmWakeupSource};
+
+/// The shortest sleep worth taking. Booting takes a few hundred ms, so
+/// shorter sleeps use more power than staying awake.
+pub const MIN_SLEEP: Duration = Duration::from_secs(1);
+
+/// How long to wait for the display to finish drawing before sleeping.
+const DISPLAY_SLEEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The active power profile. Tasks re-read this when it changes.
+pub static POWER_PROFILE: StickySignal<CriticalSectionRawMutex, PowerProfile, 4> =
+    StickySignal::new_with_name("power_profile");
+
+/// Overrides the wake interval of the power profile, if set.
+pub static WAKE_INTERVAL: StickySignal<CriticalSectionRawMutex, WakeInterval, 4> =
+    StickySignal::new_with_name("wake_interval");
+
+/// How often to wake up and redraw. These all divide an hour, so ticks
+/// can be aligned to the wall clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum WakeInterval {
+    OneMinute,
+    FiveMinutes,
+    FifteenMinutes,
+}
+
+impl WakeInterval {
+    pub fn minutes(&self) -> u64 {
+        match self {
+            WakeInterval::OneMinute => 1,
+            WakeInterval::FiveMinutes => 5,
+            WakeInterval::FifteenMinutes => 15,
+        }
+    }
+
+    pub fn as_duration(&self) -> Duration {
+        Duration::from_secs(self.minutes() * 60)
+    }
+}
+
+/// Centrally configures how often we refresh, sync and sleep.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PowerProfile {
+    /// Refresh every minute, only sync the time on cold boot.
+    #[default]
+    Normal,
+    /// Refresh every 5 minutes, only sync the time on cold boot.
+    PowerSave,
+    /// Refresh every minute, sync the time every wake and never deep sleep.
+    Performance,
+}
+
+impl PowerProfile {
+    /// How often the display should be redrawn, unless overridden
+    /// with [`set_wake_interval`].
+    pub fn refresh_interval(&self) -> WakeInterval {
+        match self {
+            PowerProfile::Normal | PowerProfile::Performance => WakeInterval::OneMinute,
+            PowerProfile::PowerSave => WakeInterval::FiveMinutes,
+        }
+    }
+
+    /// Whether to sync the time over wifi on every wake, rather than only
+    /// after a reset.
+    pub fn sync_on_wake(&self) -> bool {
+        matches!(self, PowerProfile::Performance)
+    }
+
+    /// How long to deep sleep between wakes, or `None` to stay awake.
+    ///
+    /// This matches the wake interval so the timer wakes us for each redraw.
+    pub fn sleep_interval(&self) -> Option<Duration> {
+        match self {
+            PowerProfile::Performance => None,
+            _ => Some(wake_interval().as_duration()),
+        }
+    }
+}
+
+/// Switch to a new power profile, reconfiguring the tasks that use it.
+pub fn set_power_profile(profile: PowerProfile) {
+    defmt::info!("switching to {} power profile", profile);
+    POWER_PROFILE.signal(profile);
+}
+
+/// The active power profile.
+pub fn power_profile() -> PowerProfile {
+    POWER_PROFILE.peek().unwrap_or_default()
+}
+
+/// Override how often to wake up and redraw, regardless of the power profile.
+pub fn set_wake_interval(interval: WakeInterval) {
+    defmt::info!("waking every {} minutes", interval.minutes());
+    WAKE_INTERVAL.signal(interval);
+}
+
+/// How often to wake up and redraw.
+pub fn wake_interval() -> WakeInterval {
+    WAKE_INTERVAL
+        .peek()
+        .unwrap_or_else(|| power_profile().refresh_interval())
+}
+
+/// Deep sleep until `wake_after` has passed, the PCF8563 alarm goes off, or
+/// one of `wake_buttons` is pressed. The watch starts from scratch when it
+/// wakes, and [`crate::get_wakeup_cause`] says why.
+///
+/// This waits for the display to finish drawing and go to sleep first, so
+/// the panel isn't left powered mid refresh. Sleeps shorter than
+/// [`MIN_SLEEP`] are lengthened to it.
+///
+/// The current time is saved with [`GlobalTime::persist_offset`] just
+/// before sleeping, so that on wake only the sleep itself is measured by
+/// the rtc slow clock.
+///
+/// Pins stay in their last state unless isolated. The vibration motor on
+/// GPIO17 has to be driven low before sleeping, or it may hum. The button
+/// and alarm pins are left as inputs for the rtc to watch.
+pub async fn enter_deep_sleep(
+    rtc: &mut Rtc<'_>,
+    global_time: &GlobalTime,
+    wake_after: Duration,
+    wake_buttons: &[Button],
+) -> ! {
+    if with_timeout(DISPLAY_SLEEP_TIMEOUT, crate::ui::wait_display_asleep())
+        .await
+        .is_err()
+    {
+        defmt::warn!("display didn't go to sleep, sleeping anyway");
+    }
+
+    let wake_after = wake_after.max(MIN_SLEEP);
+    defmt::info!(
+        "deep sleeping for {}s, or until {} is pressed",
+        wake_after.as_secs(),
+        wake_buttons
+    );
+
+    let timer = TimerWakeupSource::new(core::time::Duration::from_micros(wake_after.as_micros()));
+    let alarm = RtcAlarmWakeupSource;
+    let buttons = ButtonWakeupSource::new(wake_buttons);
+    global_time.persist_offset();
+    rtc.sleep_deep(&[&timer, &alarm, &buttons]);
+}
@@ -0,0 +1,120 @@
+//! A hardware self test, for bring-up after assembly.
+
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::i2c::I2c;
+use epd_waveshare::prelude::*;
+
+use crate::display::{Panel, PanelBuffer};
+use crate::BatteryStatusDriver;
+
+/// How long to buzz the motor for.
+const MOTOR_BUZZ: Duration = Duration::from_millis(300);
+
+/// The result of [`self_test`] for each subsystem.
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct SelfTestReport {
+    /// The test pattern was sent to the panel and refreshed.
+    pub display: bool,
+    /// The motor pin could be driven. The motor has no feedback, so
+    /// whether it actually buzzed has to be checked by feel.
+    pub motor: bool,
+    /// The battery voltage in mV, if it could be read.
+    pub battery: Option<u32>,
+    /// The accelerometer identified itself and returned a sample.
+    pub accel: bool,
+}
+
+impl SelfTestReport {
+    /// Whether every subsystem passed.
+    pub fn passed(&self) -> bool {
+        self.display && self.motor && self.battery.is_some() && self.accel
+    }
+}
+
+/// Exercise the display, motor, battery and accelerometer in turn.
+///
+/// The display is left showing the test pattern. The battery is reported
+/// as failed if there is no driver for it.
+pub async fn self_test<SPI, BUSY, DC, RST, DELAY, EPD, MOTOR, I2C>(
+    epd: &mut EPD,
+    spi: &mut SPI,
+    delay: &mut DELAY,
+    motor: &mut MOTOR,
+    battery: Option<&mut BatteryStatusDriver<'_>>,
+    i2c: &mut I2C,
+) -> SelfTestReport
+where
+    SPI: embedded_hal::spi::SpiDevice,
+    BUSY: embedded_hal::digital::InputPin,
+    DC: embedded_hal::digital::OutputPin,
+    RST: embedded_hal::digital::OutputPin,
+    DELAY: embedded_hal::delay::DelayNs,
+    EPD: Panel<SPI, BUSY, DC, RST, DELAY>,
+    MOTOR: OutputPin,
+    I2C: I2c,
+{
+    defmt::info!("self test: display");
+    let display = {
+        let pattern: EPD::Buffer = crate::ui::test_pattern();
+        epd.update_frame(spi, pattern.bytes(), delay)
+            .and_then(|()| epd.display_frame(spi, delay))
+            .inspect_err(|_| defmt::error!("self test: failed to draw the test pattern"))
+            .is_ok()
+    };
+
+    defmt::info!("self test: motor");
+    let motor = {
+        let on = motor.set_high().is_ok();
+        Timer::after(MOTOR_BUZZ).await;
+        let off = motor.set_low().is_ok();
+        on && off
+    };
+
+    defmt::info!("self test: battery");
+    let battery = match battery {
+        Some(battery) => match battery.status().await {
+            Ok(status) => {
+                defmt::info!("self test: battery is {}", status);
+                Some(status.voltage())
+            }
+            Err(e) => {
+                defmt::error!("self test: failed to read the battery: {}", e);
+                None
+            }
+        },
+        None => {
+            defmt::error!("self test: no battery driver");
+            None
+        }
+    };
+
+    defmt::info!("self test: accel");
+    let accel = match crate::accel::check_chip_id(i2c) {
+        Ok(true) => match crate::accel::read_accel_raw(i2c) {
+            Ok((x, y, z)) => {
+                defmt::info!("self test: accel reads x: {} y: {} z: {}", x, y, z);
+                true
+            }
+            Err(_) => {
+                defmt::error!("self test: failed to read an accel sample");
+                false
+            }
+        },
+        Ok(false) => {
+            defmt::error!("self test: accel has the wrong chip id");
+            false
+        }
+        Err(_) => {
+            defmt::error!("self test: accel did not respond");
+            false
+        }
+    };
+
+    SelfTestReport {
+        display,
+        motor,
+        battery,
+        accel,
+    }
+}
@@ -3,8 +3,13 @@ use core::future::Future;
 use core::sync::atomic::{AtomicU16, Ordering};
 use core::task::{Context, Poll, Waker};
 
+use embassy_futures::select::{select, Either};
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+use futures::{Stream, StreamExt};
+
+use crate::clock::{Clock, EmbassyClock};
 
 #[derive(Debug)]
 enum StateInner {
@@ -12,9 +17,30 @@ enum StateInner {
     Signaled,
 }
 
+/// The id and name of each waiter, and its state.
+type Waiters<const WAKERS: usize> = heapless::Vec<(u16, &'static str, StateInner), WAKERS>;
+
+/// Mark every waiter as signaled, waking the ones that are waiting.
+fn wake_waiters<const WAKERS: usize>(waiters: &mut Waiters<WAKERS>) {
+    for (_, _, state) in waiters.iter_mut() {
+        let old = core::mem::replace(state, StateInner::Signaled);
+        if let StateInner::Waiting(waker) = old {
+            waker.wake();
+        }
+    }
+}
+
+/// Remove the waiter with the given id, if it is registered.
+fn remove_waiter<const WAKERS: usize>(waiters: &mut Waiters<WAKERS>, id: u16) {
+    // swamp remove is faster than retain
+    if let Some(idx) = waiters.iter().position(|(i, _, _)| *i == id) {
+        waiters.swap_remove(idx);
+    }
+}
+
 struct State<T, const WAKERS: usize> {
     value: Option<T>,
-    waiters: heapless::Vec<(u16, StateInner), WAKERS>,
+    waiters: Waiters<WAKERS>,
 }
 
 impl<T, const WAKERS: usize> State<T, WAKERS> {
@@ -91,10 +117,7 @@ where
                 cell.waiters.len()
             );
 
-            // swamp remove is faster than retain
-            if let Some((idx, _)) = cell.waiters.iter().enumerate().find(|(_, (i, _))| *i != id) {
-                cell.waiters.swap_remove(idx);
-            }
+            remove_waiter(&mut cell.waiters, id);
         })
     }
 
@@ -102,16 +125,81 @@ where
     pub fn signal(&self, val: T) {
         self.state.lock(|cell| {
             let mut cell = cell.borrow_mut();
-            for state in cell.waiters.iter_mut() {
-                let old = core::mem::replace(state, (state.0, StateInner::Signaled));
-                if let (_, StateInner::Waiting(waker)) = old {
-                    waker.wake();
-                }
+            wake_waiters(&mut cell.waiters);
+            cell.value = Some(val);
+        })
+    }
+
+    /// Mark this StickySignal as signaled, unless it already holds an equal
+    /// value, in which case nothing happens and no waiters are woken.
+    ///
+    /// Returns whether the value changed.
+    pub fn signal_if_changed(&self, val: T) -> bool
+    where
+        T: PartialEq,
+    {
+        // compare and store under one lock, so a signal in between can't
+        // be overwritten or missed
+        let changed = self.state.lock(|cell| {
+            let mut cell = cell.borrow_mut();
+            if cell.value.as_ref() == Some(&val) {
+                return false;
             }
+            wake_waiters(&mut cell.waiters);
             cell.value = Some(val);
+            true
+        });
+
+        if !changed {
+            defmt::trace!("{}: value unchanged, not signaling", self.prefix());
+        }
+
+        changed
+    }
+
+    /// Log every live waiter and the total count, to help track down
+    /// waiters that are never dropped.
+    ///
+    /// This logs at trace level, so it compiles to nothing unless that
+    /// level is enabled.
+    pub fn dump_waiters(&self) {
+        self.state.lock(|cell| {
+            let cell = cell.borrow();
+            defmt::trace!("{}: {} waiters", self.prefix(), cell.waiters.len());
+            for (id, name, state) in cell.waiters.iter() {
+                defmt::trace!(
+                    "{}: waiter {} '{}' ({})",
+                    self.prefix(),
+                    id,
+                    name,
+                    match state {
+                        StateInner::Waiting(_) => "waiting",
+                        StateInner::Signaled => "signaled",
+                    }
+                );
+            }
         })
     }
 
+    /// The names of the live waiters, in no particular order.
+    pub fn waiter_names(&self) -> heapless::Vec<&'static str, WAKERS> {
+        self.state.lock(|cell| {
+            cell.borrow()
+                .waiters
+                .iter()
+                .map(|(_, name, _)| *name)
+                .collect()
+        })
+    }
+
+    /// Check if the StickySignal has been signaled.
+    ///
+    /// This method returns `true` if the signal has been set, and `false` otherwise.
+    /// Unlike [`StickySignal::try_take`], the value is left in place.
+    pub fn is_signaled(&self) -> bool {
+        self.state.lock(|cell| cell.borrow().value.is_some())
+    }
+
     /// Remove the queued value in this `StickySignal`, if any.
     pub fn reset(&self) {
         self.state.lock(|cell| {
@@ -153,8 +241,8 @@ where
                 .find(|(_, state)| state.0 == id);
 
             match state {
-                Some((_, (_, StateInner::Waiting(_)))) => Poll::Pending,
-                Some((idx, (_, StateInner::Signaled))) => {
+                Some((_, (_, _, StateInner::Waiting(_)))) => Poll::Pending,
+                Some((idx, (_, _, StateInner::Signaled))) => {
                     defmt::trace!(
                         "{}: removing idx {} on len {}",
                         self.prefix(),
@@ -166,7 +254,7 @@ where
                 }
                 None => {
                     s.waiters
-                        .push((id, StateInner::Waiting(cx.waker().clone())))
+                        .push((id, name, StateInner::Waiting(cx.waker().clone())))
                         .unwrap();
                     defmt::trace!(
                         "{}: registering waiter '{}' ({} total)",
@@ -190,6 +278,28 @@ where
         }
     }
 
+    /// Stream of each value as it is signaled, starting from the next one.
+    ///
+    /// A fresh waiter is registered for each item, and dropping the stream
+    /// drops the waiter along with it.
+    pub fn stream(&self, name: &'static str) -> impl Stream<Item = T> + '_ {
+        futures::stream::unfold(
+            (),
+            move |()| async move { Some((self.wait(name).await, ())) },
+        )
+    }
+
+    /// A view of this signal that applies `f` to each value, for tasks that
+    /// only care about part of it.
+    ///
+    /// The view shares this signal's waiters rather than being a second
+    /// signal, so there is no extra static to keep in sync. The tradeoff
+    /// is that `f` runs on every read, and waiters are still woken when
+    /// the projected value doesn't change.
+    pub fn map<U, F: Fn(T) -> U>(&self, f: F) -> SignalMap<'_, M, T, F, WAKERS> {
+        SignalMap { signal: self, f }
+    }
+
     /// Future that completes when f returns Some(U). This will also check
     /// the current value.
     pub async fn wait_for<U>(&self, name: &'static str, f: impl Fn(T) -> Option<U>) -> U {
@@ -207,13 +317,30 @@ where
         }
     }
 
-    /// Check if the StickySignal has been signaled.
+    /// Future that completes with the next signaled value, or `None` if
+    /// `timeout` passes first.
     ///
-    /// This method returns `true` if the signal has been set, and `false` otherwise.
-    // pub fn is_signaled(&self) -> bool {
-    //     self.state
-    //         .lock(|cell| matches!(*cell.borrow(), State::Signaled(_)))
-    // }
+    /// The waiter is dropped on timeout, so it doesn't hold a slot.
+    pub async fn wait_timeout(&self, name: &'static str, timeout: Duration) -> Option<T> {
+        self.wait_timeout_with(name, timeout, &EmbassyClock).await
+    }
+
+    /// Future that completes with the next signaled value, or `None` if
+    /// `timeout` passes on `clock` first.
+    pub async fn wait_timeout_with<C: Clock>(
+        &self,
+        name: &'static str,
+        timeout: Duration,
+        clock: &C,
+    ) -> Option<T> {
+        match select(self.wait(name), clock.sleep(timeout)).await {
+            Either::First(val) => Some(val),
+            Either::Second(()) => {
+                defmt::trace!("{}: timed out waiting for '{}'", self.prefix(), name);
+                None
+            }
+        }
+    }
 
     /// Peek at the value in this `StickySignal` without taking it.
     ///
@@ -223,6 +350,86 @@ where
     }
 }
 
+impl<M, T> StickySignal<M, T, 1>
+where
+    M: RawMutex,
+{
+    fn poll_wait_take(&self, name: &'static str, id: u16, cx: &mut Context<'_>) -> Poll<T> {
+        self.state.lock(|cell| {
+            let mut s = cell.borrow_mut();
+            let s = &mut *s;
+
+            match s.waiters.iter_mut().position(|state| state.0 == id) {
+                Some(idx) => match (
+                    matches!(s.waiters[idx].2, StateInner::Signaled),
+                    s.value.take(),
+                ) {
+                    (true, Some(value)) => {
+                        s.waiters.swap_remove(idx);
+                        Poll::Ready(value)
+                    }
+                    // the value was taken or reset before we got to it, so keep waiting
+                    (_, value) => {
+                        s.value = value;
+                        s.waiters[idx].2 = StateInner::Waiting(cx.waker().clone());
+                        Poll::Pending
+                    }
+                },
+                None => {
+                    s.waiters
+                        .push((id, name, StateInner::Waiting(cx.waker().clone())))
+                        .unwrap();
+                    defmt::trace!("{}: registering taking waiter '{}'", self.prefix(), name);
+                    Poll::Pending
+                }
+            }
+        })
+    }
+
+    /// Future that completes when this StickySignal has been signaled,
+    /// moving the value out rather than cloning it, so `T` needn't be `Clone`.
+    ///
+    /// This is only available on single waker signals, since the value can
+    /// only be taken once. Mixing it with [`StickySignal::wait`], or having
+    /// more than one of these in flight, is a logic error.
+    pub fn wait_take(&self, name: &'static str) -> WaiterTake<'_, M, T> {
+        let id = self.id.fetch_add(1, Ordering::Relaxed);
+        WaiterTake {
+            id,
+            name,
+            signal: self,
+        }
+    }
+}
+
+/// A projection of a [`StickySignal`], see [`StickySignal::map`].
+pub struct SignalMap<'a, M: RawMutex, T, F, const WAKERS: usize> {
+    signal: &'a StickySignal<M, T, WAKERS>,
+    f: F,
+}
+
+impl<'a, M, T, U, F, const WAKERS: usize> SignalMap<'a, M, T, F, WAKERS>
+where
+    M: RawMutex,
+    T: Clone + Send,
+    F: Fn(T) -> U,
+{
+    /// The projection of the current value, if there is one.
+    pub fn peek(&self) -> Option<U> {
+        self.signal.peek().map(&self.f)
+    }
+
+    /// Future that completes with the projection of the next signaled value.
+    pub async fn wait(&self, name: &'static str) -> U {
+        (self.f)(self.signal.wait(name).await)
+    }
+
+    /// Stream of the projection of each value as it is signaled.
+    pub fn stream(&self, name: &'static str) -> impl Stream<Item = U> + '_ {
+        self.signal.stream(name).map(&self.f)
+    }
+}
+
 pub struct Waiter<'a, M: RawMutex, T: Clone, const WAKERS: usize> {
     id: u16,
     name: &'static str,
@@ -244,3 +451,189 @@ impl<'a, M: RawMutex, T: Clone + Send, const WAKERS: usize> Future for Waiter<'a
         self.signal.poll_wait(self.name, self.id, cx)
     }
 }
+
+/// Future returned by [`StickySignal::wait_take`].
+pub struct WaiterTake<'a, M: RawMutex, T> {
+    id: u16,
+    name: &'static str,
+    signal: &'a StickySignal<M, T, 1>,
+}
+
+impl<'a, M: RawMutex, T> Drop for WaiterTake<'a, M, T> {
+    fn drop(&mut self) {
+        self.signal.drop_waiter(self.id);
+    }
+}
+
+impl<'a, M: RawMutex, T> Future for WaiterTake<'a, M, T> {
+    type Output = T;
+
+    fn poll(self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.signal.poll_wait_take(self.name, self.id, cx)
+    }
+}
+
+struct QueueState<T, const WAKERS: usize, const DEPTH: usize> {
+    values: heapless::Deque<T, DEPTH>,
+    waiters: Waiters<WAKERS>,
+}
+
+/// Like a [`StickySignal`], but keeps up to `DEPTH` values rather than just
+/// the latest, so values signaled in quick succession aren't lost.
+///
+/// When the queue is full, the oldest value is dropped to make room.
+/// Each value is only handed to one waiter.
+pub struct StickyQueue<M, T, const WAKERS: usize, const DEPTH: usize>
+where
+    M: RawMutex,
+{
+    state: Mutex<M, RefCell<QueueState<T, WAKERS, DEPTH>>>,
+    id: AtomicU16,
+    name: Option<&'static str>,
+}
+
+impl<M, T, const WAKERS: usize, const DEPTH: usize> StickyQueue<M, T, WAKERS, DEPTH>
+where
+    M: RawMutex,
+{
+    /// Create a new, empty `StickyQueue`.
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(QueueState {
+                values: heapless::Deque::new(),
+                waiters: heapless::Vec::new(),
+            })),
+            id: AtomicU16::new(0),
+            name: None,
+        }
+    }
+
+    pub const fn new_with_name(name: &'static str) -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(QueueState {
+                values: heapless::Deque::new(),
+                waiters: heapless::Vec::new(),
+            })),
+            id: AtomicU16::new(0),
+            name: Some(name),
+        }
+    }
+
+    fn prefix(&self) -> &'static str {
+        self.name.unwrap_or("queue")
+    }
+
+    /// Add a value to the back of the queue, dropping the oldest value if
+    /// it is full, and wake the waiters.
+    pub fn signal(&self, val: T) {
+        self.state.lock(|cell| {
+            let mut cell = cell.borrow_mut();
+            if cell.values.is_full() {
+                defmt::trace!("{}: full, dropping the oldest value", self.prefix());
+                cell.values.pop_front();
+            }
+            // there is always room after the pop above
+            let _ = cell.values.push_back(val);
+            wake_waiters(&mut cell.waiters);
+        })
+    }
+
+    /// non-blocking method to take the value at the front of the queue.
+    pub fn try_take(&self) -> Option<T> {
+        self.state.lock(|cell| cell.borrow_mut().values.pop_front())
+    }
+
+    /// How many values are queued.
+    pub fn len(&self) -> usize {
+        self.state.lock(|cell| cell.borrow().values.len())
+    }
+
+    /// Whether there are no values queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all the queued values.
+    pub fn reset(&self) {
+        self.state.lock(|cell| cell.borrow_mut().values.clear());
+    }
+
+    fn drop_waiter(&self, id: u16) {
+        self.state.lock(|cell| {
+            defmt::trace!("{}: dropping waiter '{}'", self.prefix(), id);
+            remove_waiter(&mut cell.borrow_mut().waiters, id);
+        })
+    }
+
+    fn poll_wait(&self, name: &'static str, id: u16, cx: &mut Context<'_>) -> Poll<T> {
+        self.state.lock(|cell| {
+            let mut s = cell.borrow_mut();
+
+            if let Some(val) = s.values.pop_front() {
+                remove_waiter(&mut s.waiters, id);
+                return Poll::Ready(val);
+            }
+
+            // another waiter may have taken the value we were woken for
+            let waker = StateInner::Waiting(cx.waker().clone());
+            match s.waiters.iter_mut().find(|state| state.0 == id) {
+                Some(state) => state.2 = waker,
+                None => {
+                    s.waiters.push((id, name, waker)).unwrap();
+                    defmt::trace!(
+                        "{}: registering waiter '{}' ({} total)",
+                        self.prefix(),
+                        name,
+                        s.waiters.len()
+                    );
+                }
+            }
+            Poll::Pending
+        })
+    }
+
+    /// Future that completes with the value at the front of the queue,
+    /// waiting for one to be signaled if it is empty.
+    pub fn wait(&self, name: &'static str) -> QueueWaiter<'_, M, T, WAKERS, DEPTH> {
+        let id = self.id.fetch_add(1, Ordering::Relaxed);
+        QueueWaiter {
+            id,
+            name,
+            queue: self,
+        }
+    }
+}
+
+impl<M, T, const WAKERS: usize, const DEPTH: usize> Default for StickyQueue<M, T, WAKERS, DEPTH>
+where
+    M: RawMutex,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by [`StickyQueue::wait`].
+pub struct QueueWaiter<'a, M: RawMutex, T, const WAKERS: usize, const DEPTH: usize> {
+    id: u16,
+    name: &'static str,
+    queue: &'a StickyQueue<M, T, WAKERS, DEPTH>,
+}
+
+impl<'a, M: RawMutex, T, const WAKERS: usize, const DEPTH: usize> Drop
+    for QueueWaiter<'a, M, T, WAKERS, DEPTH>
+{
+    fn drop(&mut self) {
+        self.queue.drop_waiter(self.id);
+    }
+}
+
+impl<'a, M: RawMutex, T, const WAKERS: usize, const DEPTH: usize> Future
+    for QueueWaiter<'a, M, T, WAKERS, DEPTH>
+{
+    type Output = T;
+
+    fn poll(self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.queue.poll_wait(self.name, self.id, cx)
+    }
+}
@@ -0,0 +1,121 @@
+//! A stopwatch, controlled by the buttons and drawn by the display task.
+
+use core::cell::RefCell;
+
+use embassy_futures::select::select;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::sticky_signal::StickySignal;
+
+/// How often to redraw the display while running.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(500);
+
+static STOPWATCH: Mutex<CriticalSectionRawMutex, RefCell<Stopwatch>> =
+    Mutex::new(RefCell::new(Stopwatch::new()));
+
+/// Whether the stopwatch is running, used to start and stop the redraws.
+static RUNNING: StickySignal<CriticalSectionRawMutex, bool, 2> =
+    StickySignal::new_with_name("stopwatch_running");
+
+struct Stopwatch {
+    /// When the stopwatch was last started, if it is running.
+    started: Option<Instant>,
+    /// Time accumulated before the last start.
+    elapsed: Duration,
+}
+
+impl Stopwatch {
+    const fn new() -> Self {
+        Self {
+            started: None,
+            elapsed: Duration::from_ticks(0),
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        match self.started {
+            Some(started) => self.elapsed + started.elapsed(),
+            None => self.elapsed,
+        }
+    }
+}
+
+/// Start the stopwatch if it is stopped, or stop it if it is running.
+pub fn toggle() {
+    let running = STOPWATCH.lock(|cell| {
+        let mut stopwatch = cell.borrow_mut();
+        match stopwatch.started.take() {
+            Some(started) => {
+                stopwatch.elapsed += started.elapsed();
+                false
+            }
+            None => {
+                stopwatch.started = Some(Instant::now());
+                true
+            }
+        }
+    });
+    defmt::info!("stopwatch running: {}", running);
+    RUNNING.signal(running);
+    crate::request_redraw();
+}
+
+/// Stop the stopwatch and clear the elapsed time.
+pub fn reset() {
+    STOPWATCH.lock(|cell| *cell.borrow_mut() = Stopwatch::new());
+    RUNNING.signal(false);
+    crate::request_redraw();
+}
+
+/// Whether the stopwatch is currently running.
+pub fn is_running() -> bool {
+    STOPWATCH.lock(|cell| cell.borrow().started.is_some())
+}
+
+/// The total time on the stopwatch, or `None` if it has been reset.
+pub fn elapsed() -> Option<Duration> {
+    STOPWATCH.lock(|cell| {
+        let stopwatch = cell.borrow();
+        (stopwatch.started.is_some() || stopwatch.elapsed.as_ticks() > 0)
+            .then(|| stopwatch.elapsed())
+    })
+}
+
+/// Format a duration as `MM:SS.t`.
+pub fn format_elapsed(elapsed: Duration) -> heapless::String<8> {
+    let tenths = elapsed.as_millis() / 100;
+    let (minutes, seconds, tenths) = ((tenths / 600) % 100, (tenths / 10) % 60, tenths % 10);
+
+    let mut string = heapless::String::new();
+    for (value, separator) in [(minutes, ":"), (seconds, ".")] {
+        if value < 10 {
+            ufmt::uwrite!(string, "0{}{}", value, separator).unwrap();
+        } else {
+            ufmt::uwrite!(string, "{}{}", value, separator).unwrap();
+        }
+    }
+    ufmt::uwrite!(string, "{}", tenths).unwrap();
+    string
+}
+
+/// Request redraws twice a second while the stopwatch is running.
+///
+/// No redraws are requested while it is stopped.
+#[embassy_executor::task]
+pub async fn drive_stopwatch() {
+    loop {
+        RUNNING
+            .wait_for("stopwatch started", |running| running.then_some(()))
+            .await;
+
+        while is_running() {
+            crate::request_redraw();
+            select(
+                Timer::after(REDRAW_INTERVAL),
+                RUNNING.wait_for("stopwatch stopped", |running| (!running).then_some(())),
+            )
+            .await;
+        }
+    }
+}
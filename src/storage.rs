@@ -0,0 +1,96 @@
+//! Settings that survive reflashing the app, kept in a dedicated flash region.
+//!
+//! The region is the `watchy` data partition in `partitions.csv`, which
+//! espflash writes in place of the default table. It sits after the app
+//! rather than reusing `nvs`, which belongs to the esp-idf nvs format.
+//! The record is a fixed layout at the start of the partition.
+
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::{FlashStorage, FlashStorageError};
+
+/// Where the wifi credentials are stored in flash, the start of the
+/// `watchy` partition. Keep this in sync with `partitions.csv`.
+const CREDENTIALS_OFFSET: u32 = 0x3f_0000;
+
+/// Marks a valid credentials record. Erased flash reads as all ones.
+const CREDENTIALS_MAGIC: u32 = 0x7769_6669;
+
+const SSID_LEN: usize = 32;
+const PASSWORD_LEN: usize = 64;
+
+/// The size of a stored record: the magic, then each field as a length
+/// byte followed by its padded contents, rounded up to a whole word.
+pub const CREDENTIALS_RECORD_LEN: usize = (4 + 1 + SSID_LEN + 1 + PASSWORD_LEN).next_multiple_of(4);
+
+/// The network to join, sized to match what esp-wifi accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub ssid: heapless::String<SSID_LEN>,
+    pub password: heapless::String<PASSWORD_LEN>,
+}
+
+impl Credentials {
+    /// Create credentials, or `None` if either is too long.
+    pub fn new(ssid: &str, password: &str) -> Option<Self> {
+        Some(Self {
+            ssid: ssid.try_into().ok()?,
+            password: password.try_into().ok()?,
+        })
+    }
+
+    /// Encode into the record stored in flash.
+    pub fn to_bytes(&self) -> [u8; CREDENTIALS_RECORD_LEN] {
+        let mut bytes = [0; CREDENTIALS_RECORD_LEN];
+        bytes[..4].copy_from_slice(&CREDENTIALS_MAGIC.to_le_bytes());
+
+        let mut at = 4;
+        for (field, len) in [
+            (self.ssid.as_str(), SSID_LEN),
+            (self.password.as_str(), PASSWORD_LEN),
+        ] {
+            bytes[at] = field.len() as u8;
+            bytes[at + 1..at + 1 + field.len()].copy_from_slice(field.as_bytes());
+            at += 1 + len;
+        }
+
+        bytes
+    }
+
+    /// Decode a record read from flash, or `None` if it is blank or corrupt.
+    pub fn from_bytes(bytes: &[u8; CREDENTIALS_RECORD_LEN]) -> Option<Self> {
+        if bytes[..4] != CREDENTIALS_MAGIC.to_le_bytes() {
+            return None;
+        }
+
+        let field = |at: usize, max: usize| {
+            let len = bytes[at] as usize;
+            (len <= max)
+                .then(|| core::str::from_utf8(&bytes[at + 1..at + 1 + len]).ok())
+                .flatten()
+        };
+
+        let ssid = field(4, SSID_LEN)?;
+        let password = field(4 + 1 + SSID_LEN, PASSWORD_LEN)?;
+        Self::new(ssid, password)
+    }
+}
+
+/// Read the saved wifi credentials, or `None` if none have been saved.
+pub fn load_wifi_credentials() -> Option<Credentials> {
+    let mut bytes = [0; CREDENTIALS_RECORD_LEN];
+    if let Err(e) = FlashStorage::new().read(CREDENTIALS_OFFSET, &mut bytes) {
+        defmt::error!(
+            "failed to read wifi credentials: {:?}",
+            defmt::Debug2Format(&e)
+        );
+        return None;
+    }
+
+    Credentials::from_bytes(&bytes)
+}
+
+/// Save wifi credentials, replacing any already saved.
+pub fn save_wifi_credentials(credentials: &Credentials) -> Result<(), FlashStorageError> {
+    defmt::info!("saving wifi credentials for {}", credentials.ssid.as_str());
+    FlashStorage::new().write(CREDENTIALS_OFFSET, &credentials.to_bytes())
+}
@@ -1,49 +1,358 @@
-use chrono::{NaiveDateTime, Timelike};
+use core::cell::Cell;
+
 use embassy_futures::select;
-use embassy_net::{udp::UdpSocket, IpAddress};
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embedded_nal_async::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use embedded_hal::i2c::I2c;
+use esp_hal::macros::ram;
 use esp_hal::rtc_cntl::Rtc;
 
+use crate::pcf8563::{Pcf8563, RtcError};
 use crate::sticky_signal::StickySignal;
-use esp_wifi::wifi::ipv4::ToSocketAddrs;
 
-use futures::Stream;
-use sntpc::{NtpContext, NtpResult, NtpTimestampGenerator};
+use futures::{Stream, StreamExt};
+use sntpc::NtpResult;
+use time::{OffsetDateTime, UtcOffset};
+
+#[cfg(feature = "wifi")]
+mod ntp;
+mod timezone;
+
+#[cfg(feature = "wifi")]
+pub use ntp::{
+    bind_ntp_socket, get_default_time, get_time, get_time_host, get_time_with_retry,
+    sync_time_with_retry, NtpSocketBuffers, DEFAULT_NTP_HOST, DEFAULT_NTP_SERVERS,
+};
+pub use timezone::{DstRule, Timezone, TransitionRule, Week};
+
+/// The timezone used until one is set with [`GlobalTime::set_timezone`].
+pub const DEFAULT_TIMEZONE: Timezone = Timezone::fixed(match UtcOffset::from_hms(1, 0, 0) {
+    Ok(v) => v,
+    Err(_) => panic!("Bad value"),
+});
+
+/// The timezone that local time is displayed in, or unset for
+/// [`DEFAULT_TIMEZONE`].
+static TIMEZONE: StickySignal<CriticalSectionRawMutex, Timezone, 4> =
+    StickySignal::new_with_name("timezone");
 
 /// The estimated offset between system time and real time.
 ///
 /// This number is usually determined using an ntp server and
-/// updated later.
-static TIME_OFFSET: StickySignal<CriticalSectionRawMutex, u64, 4> =
+/// updated later. It is signed since the system clock may be
+/// ahead of or behind real time.
+static TIME_OFFSET: StickySignal<CriticalSectionRawMutex, i64, 4> =
     StickySignal::new_with_name("time_offset");
 
+/// Pulsed on every wall clock minute with the time of that minute, as
+/// from [`GlobalTime::get_time`], for tasks that need to run each minute
+/// without drifting relative to each other. See [`drive_minute_tick`].
+pub static MINUTE_TICK: StickySignal<CriticalSectionRawMutex, u64, 8> =
+    StickySignal::new_with_name("minute_tick");
+
+/// When [`TIME_OFFSET`] was last updated, in real microseconds since the
+/// unix epoch, so that it stays meaningful across deep sleep.
+static LAST_SYNC: Mutex<CriticalSectionRawMutex, Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+/// Marks [`PERSISTED_TIME`] as valid, since rtc memory is garbage after a
+/// power on reset.
+const PERSISTED_MAGIC: u32 = 0x7761_7463;
+
+/// The real time saved by [`GlobalTime::persist_offset`].
+///
+/// The system timer restarts on wake but the rtc keeps counting through
+/// deep sleep, so the real time is saved against the rtc rather than
+/// saving the offset itself.
+#[derive(Clone, Copy)]
+struct PersistedTime {
+    magic: u32,
+    /// The rtc time when this was saved, in microseconds.
+    rtc_micros: u64,
+    /// The real time when this was saved, in microseconds since the epoch.
+    real_micros: u64,
+    /// See [`LAST_SYNC`].
+    last_sync: u64,
+}
+
+#[ram(rtc_slow, persistent)]
+static mut PERSISTED_TIME: PersistedTime = PersistedTime {
+    magic: 0,
+    rtc_micros: 0,
+    real_micros: 0,
+    last_sync: 0,
+};
+
+/// Details of the last ntp sync.
+static LAST_NTP_SYNC: Mutex<CriticalSectionRawMutex, Cell<Option<NtpSync>>> =
+    Mutex::new(Cell::new(None));
+
+/// Diagnostics about an ntp sync, for showing where the time came from.
+///
+/// `sntpc` does not expose the server's reference id, so only the
+/// stratum and precision are kept.
+#[derive(Debug, Clone, Copy)]
+pub struct NtpSync {
+    /// The corrected time at the moment of the sync, in UTC.
+    pub time: OffsetDateTime,
+    /// How many hops the server is from a reference clock.
+    pub stratum: u8,
+    /// The precision of the server clock, as a power of two in seconds.
+    pub precision: i8,
+    /// How long the request took to get to the server and back.
+    pub roundtrip: Duration,
+    /// The correction applied to the system clock, in µs.
+    pub offset_micros: i64,
+    /// When the sync happened, in system time.
+    pub at: Instant,
+}
+
+impl NtpSync {
+    /// How far off the synced time may be, which is half the round trip
+    /// since the reply could have been delayed either way.
+    pub fn uncertainty(&self) -> Duration {
+        self.roundtrip / 2
+    }
+}
+
 /// A time struct. This is initialized to empty and is updated when
 /// the time changes.
 #[derive(Clone, Copy)]
 pub struct GlobalTime {
     rtc: &'static Rtc<'static>,
+    /// A second timezone to show alongside local time, if any.
+    secondary: Option<SecondaryTimezone>,
+}
+
+/// A labelled timezone, for showing a world clock next to local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecondaryTimezone {
+    /// A short name for the zone, such as `NYC`.
+    pub label: &'static str,
+    pub timezone: Timezone,
+}
+
+impl SecondaryTimezone {
+    /// A secondary timezone with a fixed offset from UTC.
+    pub const fn fixed(label: &'static str, offset: UtcOffset) -> Self {
+        Self {
+            label,
+            timezone: Timezone::fixed(offset),
+        }
+    }
 }
 
 impl GlobalTime {
+    /// Create the global time, restoring the offset from before deep
+    /// sleep if there is one.
     pub fn new(rtc: &'static Rtc) -> Self {
-        Self { rtc }
+        let time = Self {
+            rtc,
+            secondary: None,
+        };
+        time.restore_offset();
+        time
+    }
+
+    /// Also keep time in a second timezone, see [`GlobalTime::secondary_time`].
+    pub fn with_secondary(self, secondary: SecondaryTimezone) -> Self {
+        Self {
+            secondary: Some(secondary),
+            ..self
+        }
+    }
+
+    /// Set the offset between system time and real time.
+    ///
+    /// Re-applying the current offset doesn't restart the
+    /// [`GlobalTime::minutes`] streams.
+    pub fn init_offset(&self, offset_micros: i64) {
+        TIME_OFFSET.signal_if_changed(offset_micros);
+        LAST_SYNC.lock(|cell| cell.set(Some(self.get_time())));
+        self.persist_offset();
+    }
+
+    /// How long it has been since the offset was last set, or `None` if
+    /// the time has never been synced.
+    pub fn time_since_sync(&self) -> Option<Duration> {
+        LAST_SYNC
+            .lock(|cell| cell.get())
+            .map(|synced| Duration::from_micros(self.get_time().saturating_sub(synced)))
+    }
+
+    /// Save the current time to rtc memory, so that it survives deep sleep
+    /// and can be picked up with [`GlobalTime::restore_offset`] on wake.
+    ///
+    /// This is done whenever the offset changes, and again by
+    /// [`crate::power::enter_deep_sleep`] so the saved time is fresh.
+    pub fn persist_offset(&self) {
+        let Some(last_sync) = LAST_SYNC.lock(|cell| cell.get()) else {
+            return;
+        };
+
+        let persisted = PersistedTime {
+            magic: PERSISTED_MAGIC,
+            rtc_micros: self.rtc.get_time_us(),
+            real_micros: self.get_time(),
+            last_sync,
+        };
+
+        // SAFETY: only accessed from here and restore_offset, by copy
+        unsafe { PERSISTED_TIME = persisted };
+    }
+
+    /// Restore the offset saved by [`GlobalTime::persist_offset`], returning
+    /// whether there was one.
+    ///
+    /// Nothing is restored after a power on reset, since the rtc memory
+    /// and the rtc itself start over.
+    pub fn restore_offset(&self) -> bool {
+        // SAFETY: only accessed from here and persist_offset, by copy
+        let persisted = unsafe { PERSISTED_TIME };
+        if persisted.magic != PERSISTED_MAGIC {
+            defmt::info!("no saved time offset");
+            return false;
+        }
+
+        let Some(slept) = self.rtc.get_time_us().checked_sub(persisted.rtc_micros) else {
+            defmt::warn!("rtc went backwards, ignoring saved time offset");
+            return false;
+        };
+
+        let real_micros = persisted.real_micros + slept;
+        let microseconds = esp_hal::time::now().duration_since_epoch().to_micros();
+        let Ok(offset) = i64::try_from(real_micros as i128 - microseconds as i128) else {
+            defmt::warn!("saved time offset is out of range, ignoring");
+            return false;
+        };
+
+        defmt::info!("restored time offset {}", offset);
+        TIME_OFFSET.signal_if_changed(offset);
+        LAST_SYNC.lock(|cell| cell.set(Some(persisted.last_sync)));
+        true
+    }
+
+    /// Apply the result of an ntp request, updating the offset and rtc
+    /// and keeping its details for diagnostics.
+    pub fn sync(&self, result: &NtpResult) {
+        self.init_offset(result.offset);
+        self.init_time(result.seconds, result.seconds_fraction);
+
+        let sync = NtpSync {
+            time: datetime_from_micros(self.get_time()),
+            stratum: result.stratum,
+            precision: result.precision,
+            roundtrip: Duration::from_micros(result.roundtrip()),
+            offset_micros: result.offset(),
+            at: Instant::now(),
+        };
+        defmt::info!(
+            "synced time from stratum {} server (precision 2^{}s, roundtrip {}us)",
+            sync.stratum,
+            sync.precision,
+            sync.roundtrip.as_micros()
+        );
+        LAST_NTP_SYNC.lock(|cell| cell.set(Some(sync)));
+    }
+
+    /// Write the current time, in UTC, to the external RTC so that it
+    /// survives deep sleep and resets without syncing again.
+    ///
+    /// Nothing is written if the time has never been synced, since it
+    /// would only be the uptime.
+    pub fn persist_to_rtc<I2C: I2c>(&self, rtc: &mut Pcf8563<I2C>) -> Result<(), I2C::Error> {
+        if self.time_since_sync().is_none() {
+            defmt::warn!("time isn't synced, not writing it to the rtc");
+            return Ok(());
+        }
+
+        let time = datetime_from_micros(self.get_time());
+        rtc.set_time(time)?;
+        defmt::info!("wrote time {} to the rtc", time.unix_timestamp());
+        Ok(())
+    }
+
+    /// Set the time from the external RTC, as written by
+    /// [`GlobalTime::persist_to_rtc`], so there is a sane clock before
+    /// the network comes up.
+    ///
+    /// If the RTC has lost power, its time is ignored and the offset is
+    /// left as it was.
+    pub fn init_from_rtc<I2C: I2c>(
+        &self,
+        rtc: &mut Pcf8563<I2C>,
+    ) -> Result<(), RtcError<I2C::Error>> {
+        let time = rtc.get_time()?;
+        defmt::info!("read time {} from the rtc", time.unix_timestamp());
+        self.set_time(time);
+        Ok(())
+    }
+
+    /// Details of the last ntp sync, or `None` if there hasn't been one.
+    pub fn last_ntp_sync(&self) -> Option<NtpSync> {
+        LAST_NTP_SYNC.lock(|cell| cell.get())
+    }
+
+    /// The round trip of the last ntp sync, see [`NtpSync::roundtrip`].
+    pub fn sync_roundtrip(&self) -> Option<Duration> {
+        self.last_ntp_sync().map(|sync| sync.roundtrip)
+    }
+
+    /// The clock correction of the last ntp sync in µs, see
+    /// [`NtpSync::offset_micros`].
+    pub fn sync_offset(&self) -> Option<i64> {
+        self.last_ntp_sync().map(|sync| sync.offset_micros)
     }
 
-    pub fn init_offset(&self, offset_micros: u64) {
-        TIME_OFFSET.signal(offset_micros);
+    /// How long ago the last ntp sync was, in system time.
+    pub fn time_since_ntp_sync(&self) -> Option<Duration> {
+        self.last_ntp_sync().map(|sync| sync.at.elapsed())
     }
 
     pub fn init_time(&self, seconds: u32, seconds_fraction: u32) {
         // a single second fraction is 0.2 ns
-        let current_time = NaiveDateTime::from_timestamp(seconds.into(), seconds_fraction / 5);
+        let nanos = seconds_fraction / 5;
+        let current_time = datetime_from_micros(seconds as u64 * 1_000_000 + nanos as u64 / 1_000);
+        self.set_rtc(current_time);
+    }
+
+    /// Manually set the current time, for example from the buttons when
+    /// there is no network to sync with.
+    ///
+    /// This updates the offset, so any [`GlobalTime::minutes`] streams
+    /// restart with the new time.
+    pub fn set_time(&self, time: OffsetDateTime) {
+        let target = time.unix_timestamp_nanos() / 1_000;
+        let microseconds = esp_hal::time::now().duration_since_epoch().to_micros();
+
+        let Ok(offset) = i64::try_from(target - microseconds as i128) else {
+            defmt::warn!("time {} is out of range, ignoring", time.unix_timestamp());
+            return;
+        };
+
+        self.init_offset(offset);
+        self.set_rtc(time);
+    }
+
+    fn set_rtc(&self, current_time: OffsetDateTime) {
         defmt::info!(
             "time is {}:{}:{}",
             current_time.hour(),
             current_time.minute(),
             current_time.second()
         );
-        self.rtc.set_current_time(current_time);
+
+        // the rtc speaks chrono, so only convert at the boundary
+        let Some(current_time) = chrono::DateTime::from_timestamp(
+            current_time.unix_timestamp(),
+            current_time.nanosecond(),
+        ) else {
+            defmt::warn!(
+                "time {} out of range for rtc",
+                current_time.unix_timestamp()
+            );
+            return;
+        };
+        self.rtc.set_current_time(current_time.naive_utc());
     }
 
     /// Get the time based on the system time + offset
@@ -52,24 +361,111 @@ impl GlobalTime {
 
         let offset = TIME_OFFSET.peek().unwrap_or_default();
 
+        let Some(time) = microseconds.checked_add_signed(offset) else {
+            defmt::warn!(
+                "time offset {} overflows system time {}, ignoring",
+                offset,
+                microseconds
+            );
+            return microseconds;
+        };
+
+        defmt::info!("time is {} + {} = {}", microseconds, offset, time);
+        time
+    }
+
+    /// Get the current local time.
+    pub fn now(&self) -> OffsetDateTime {
+        self.local_time(self.get_time())
+    }
+
+    /// Convert a time from [`GlobalTime::get_time`] into local time.
+    pub fn local_time(&self, micros: u64) -> OffsetDateTime {
+        let utc = datetime_from_micros(micros);
+        utc.to_offset(self.timezone().offset_at(utc))
+    }
+
+    /// Change the timezone that local time is shown in, redrawing the
+    /// display. A plain `UtcOffset` can be passed for a fixed offset.
+    pub fn set_timezone(&self, timezone: impl Into<Timezone>) {
+        let timezone = timezone.into();
         defmt::info!(
-            "time is {} + {} = {}",
-            microseconds,
-            offset,
-            microseconds + offset
+            "timezone is now utc+{}s",
+            timezone.std_offset.whole_seconds()
         );
-        microseconds + offset
+        if TIMEZONE.signal_if_changed(timezone) {
+            crate::request_redraw();
+        }
     }
 
-    /// Produces a stream that terminates either when the offset is updated,
-    /// or never.
+    /// Switch daylight saving rules, keeping the current base offset.
+    pub fn set_dst_rule(&self, rule: DstRule) {
+        defmt::info!("daylight saving rule is now {}", rule);
+        self.set_timezone(rule.timezone(self.timezone().std_offset));
+    }
+
+    /// The timezone that local time is shown in.
+    pub fn timezone(&self) -> Timezone {
+        TIMEZONE.peek().unwrap_or(DEFAULT_TIMEZONE)
+    }
+
+    /// Convert a time from [`GlobalTime::get_time`] into the secondary
+    /// timezone, along with its label, if one is configured.
+    pub fn secondary_time(&self, micros: u64) -> Option<(&'static str, OffsetDateTime)> {
+        let secondary = self.secondary?;
+        let utc = datetime_from_micros(micros);
+        Some((
+            secondary.label,
+            utc.to_offset(secondary.timezone.offset_at(utc)),
+        ))
+    }
+
+    /// Produces a stream that ticks at the current
+    /// [`wake_interval`](crate::power::wake_interval), aligned to the wall
+    /// clock, and terminates either when the offset, power profile or wake
+    /// interval is updated, or never.
     ///
-    /// TODO: make sure the first one starts on the minute
+    /// See [`GlobalTime::every`] for how ticks are aligned.
     pub fn minutes(&self) -> impl Stream<Item = u64> + '_ {
-        let ticker = embassy_time::Ticker::every(embassy_time::Duration::from_secs(60));
-        futures::stream::unfold(ticker, move |mut ticker| async move {
-            match select::select(ticker.next(), TIME_OFFSET.wait("time offset updated")).await {
-                select::Either::First(()) => Some((self.get_time(), ticker)),
+        let interval = crate::power::wake_interval().as_duration();
+
+        self.every(interval).take_until(async {
+            match select::select(
+                crate::power::POWER_PROFILE.wait("power profile updated"),
+                crate::power::WAKE_INTERVAL.wait("wake interval updated"),
+            )
+            .await
+            {
+                select::Either::First(_) => defmt::info!("power profile changed, exiting"),
+                select::Either::Second(_) => defmt::info!("wake interval changed, exiting"),
+            }
+        })
+    }
+
+    /// Produces a stream that ticks every `period`, aligned to the wall
+    /// clock, and terminates when the offset is updated so that callers
+    /// can realign, or never.
+    ///
+    /// Each tick is rounded to the nearest period boundary so that a tick
+    /// that fires slightly early doesn't show the previous one.
+    pub fn every(&self, period: Duration) -> impl Stream<Item = u64> + '_ {
+        let micros = period.as_micros().max(1);
+
+        // the first tick is on the next boundary, and every period after that
+        let until_boundary = micros - self.get_time() % micros;
+        let first_tick = Instant::now() + Duration::from_micros(until_boundary);
+
+        futures::stream::unfold(first_tick, move |next_tick| async move {
+            match select::select(
+                embassy_time::Timer::at(next_tick),
+                TIME_OFFSET.wait("time offset updated"),
+            )
+            .await
+            {
+                select::Either::First(()) => {
+                    let time = round_to(self.get_time(), micros);
+                    Some((time, next_tick + period))
+                }
                 select::Either::Second(_) => {
                     defmt::info!("offset changed, exiting");
                     None
@@ -79,113 +475,105 @@ impl GlobalTime {
     }
 }
 
-#[derive(Copy, Clone, Default)]
-struct StdTimestampGen {
-    duration: core::time::Duration,
-}
+/// Pulse [`MINUTE_TICK`] on each minute boundary, realigning whenever the
+/// offset changes.
+#[embassy_executor::task]
+pub async fn drive_minute_tick(global_time: GlobalTime) {
+    const MINUTE: u64 = 60_000_000;
+    let mut last_tick = None;
 
-impl NtpTimestampGenerator for StdTimestampGen {
-    fn init(&mut self) {
-        let microseconds = esp_hal::time::now().duration_since_epoch().to_micros();
-        self.duration = core::time::Duration::from_micros(microseconds);
-    }
+    loop {
+        let until_boundary = MINUTE - global_time.get_time() % MINUTE;
+        let next_tick = Instant::now() + Duration::from_micros(until_boundary);
 
-    fn timestamp_sec(&self) -> u64 {
-        self.duration.as_secs()
-    }
-
-    fn timestamp_subsec_micros(&self) -> u32 {
-        self.duration.subsec_micros()
+        match select::select(
+            embassy_time::Timer::at(next_tick),
+            TIME_OFFSET.wait("minute tick offset updated"),
+        )
+        .await
+        {
+            select::Either::First(()) => {
+                // a timer that fires slightly early is rounded up to the
+                // boundary, so don't tick the same minute twice
+                let tick = round_to(global_time.get_time(), MINUTE);
+                if last_tick != Some(tick) {
+                    last_tick = Some(tick);
+                    MINUTE_TICK.signal(tick);
+                }
+            }
+            select::Either::Second(_) => {
+                defmt::info!("offset changed, realigning minute tick");
+            }
+        }
     }
 }
 
-const NTP_SERVER: (u8, u8, u8, u8) = (185, 83, 169, 27);
-const NTP_PORT: u16 = 123;
+/// How often [`time_resync_task`] syncs by default, to correct drift.
+#[cfg(feature = "wifi")]
+pub const DEFAULT_RESYNC_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
 
-struct EspWifiUdpSocket<'a> {
-    socket: UdpSocket<'a>,
-}
+/// How soon to retry after a failed resync, doubling up to the interval.
+#[cfg(feature = "wifi")]
+const RESYNC_RETRY_DELAY: Duration = Duration::from_secs(60);
 
-impl<'a> EspWifiUdpSocket<'a> {
-    fn new(socket: UdpSocket<'a>) -> Self {
-        Self { socket }
-    }
-}
+/// How long to wait for the network task to answer a resync.
+#[cfg(feature = "wifi")]
+const RESYNC_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// Sync the time over ntp every `interval`, to correct the drift of the
+/// system clock over long uptimes.
+///
+/// Failed syncs are retried sooner, backing off from a minute up to the
+/// interval. Syncs are skipped, and retried the same way, while wifi is
+/// down (see [`crate::wifi_available`]).
+#[cfg(feature = "wifi")]
+#[embassy_executor::task]
+pub async fn time_resync_task(global_time: GlobalTime, interval: Duration) {
+    let mut delay = interval;
+    let mut retry_delay = RESYNC_RETRY_DELAY;
 
-impl sntpc::async_impl::NtpUdpSocket for EspWifiUdpSocket<'_> {
-    async fn send_to<T: ToSocketAddrs + Send>(&self, buf: &[u8], addr: T) -> sntpc::Result<usize> {
-        let addrs = addr.to_socket_addrs().unwrap().next().unwrap();
-        let port = addrs.port();
-        let IpAddr::V4(addr) = addrs.ip() else {
-            panic!("we do not support ipv6");
+    loop {
+        embassy_time::Timer::after(delay).await;
+
+        let synced = if !crate::wifi::wifi_available() {
+            defmt::info!("wifi is down, skipping resync");
+            false
+        } else {
+            match embassy_time::with_timeout(RESYNC_TIMEOUT, crate::wifi::get_time()).await {
+                Ok(Some(result)) => {
+                    global_time.sync(&result);
+                    true
+                }
+                Ok(None) | Err(_) => false,
+            }
         };
-        let [a, b, c, d] = addr.octets();
-        self.socket
-            .send_to(
-                buf,
-                (
-                    smoltcp::wire::IpAddress::from(smoltcp::wire::Ipv4Address::new(a, b, c, d)),
-                    port,
-                ),
-            )
-            .await
-            .map_err(|e| {
-                defmt::error!("error during time send: {}", e);
-                sntpc::Error::Network
-            })?;
-        Ok(buf.len())
-    }
 
-    async fn recv_from(&self, buf: &mut [u8]) -> sntpc::Result<(usize, SocketAddr)> {
-        self.socket
-            .recv_from(buf)
-            .await
-            .map(|(bytes, meta)| {
-                let IpAddress::Ipv4(smoltcp::wire::Ipv4Address([a, b, c, d])) = meta.endpoint.addr;
-                (
-                    bytes,
-                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), meta.endpoint.port),
-                )
-            })
-            .map_err(|e| {
-                defmt::error!("error during time recv: {}", e);
-                sntpc::Error::Network
-            })
+        if synced {
+            delay = interval;
+            retry_delay = RESYNC_RETRY_DELAY;
+        } else {
+            delay = retry_delay.min(interval);
+            retry_delay = (retry_delay * 2).min(interval);
+            defmt::warn!("resync failed, retrying in {}s", delay.as_secs());
+        }
     }
 }
 
-impl core::fmt::Debug for EspWifiUdpSocket<'_> {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "EspWifiUpdSocket")
-    }
+/// How long it has been since boot.
+///
+/// Unlike [`GlobalTime::get_time`] this doesn't depend on the offset,
+/// so it is meaningful before the time is synced.
+pub fn uptime() -> Duration {
+    Duration::from_micros(esp_hal::time::now().duration_since_epoch().to_micros())
 }
 
-pub async fn get_time(socket: UdpSocket<'_>) -> Option<NtpResult> {
-    let server_socket_addr = SocketAddr::V4(SocketAddrV4::new(
-        Ipv4Addr::new(NTP_SERVER.0, NTP_SERVER.1, NTP_SERVER.2, NTP_SERVER.3),
-        NTP_PORT,
-    ));
-    let socket = EspWifiUdpSocket::new(socket);
+/// Round a time to the nearest multiple of `period`.
+fn round_to(time: u64, period: u64) -> u64 {
+    (time + period / 2) / period * period
+}
 
-    let context = NtpContext::new(StdTimestampGen::default());
-    sntpc::async_impl::get_time(server_socket_addr, socket, context)
-        .await
-        .inspect_err(|e| {
-            defmt::error!(
-                "failed to get time {}",
-                match e {
-                    sntpc::Error::IncorrectOriginTimestamp => "incorrect origin",
-                    sntpc::Error::IncorrectMode => "incorrect mode",
-                    sntpc::Error::IncorrectLeapIndicator => "incorrect leap",
-                    sntpc::Error::IncorrectResponseVersion => "incorrect response",
-                    sntpc::Error::IncorrectStratumHeaders => "incorrect stratum",
-                    sntpc::Error::IncorrectPayload => "incorrect payload",
-                    sntpc::Error::Network => "network",
-                    sntpc::Error::AddressResolve => "address resolve",
-                    sntpc::Error::ResponseAddressMismatch => "response mismatch",
-                    _ => "unknown",
-                }
-            );
-        })
-        .ok()
+/// Convert microseconds since the unix epoch into a UTC datetime.
+fn datetime_from_micros(micros: u64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp_nanos(micros as i128 * 1_000)
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
 }
@@ -0,0 +1,312 @@
+//! Fetching the time from an ntp server.
+
+use embassy_net::dns::DnsQueryType;
+use embassy_net::driver::Driver;
+use embassy_net::udp::{BindError, PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Stack};
+use embassy_time::{with_timeout, Duration, TimeoutError, Timer};
+use embedded_nal_async::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use esp_wifi::wifi::ipv4::ToSocketAddrs;
+use sntpc::{NtpContext, NtpResult, NtpTimestampGenerator};
+
+use super::GlobalTime;
+
+#[derive(Copy, Clone, Default)]
+struct StdTimestampGen {
+    duration: core::time::Duration,
+}
+
+impl NtpTimestampGenerator for StdTimestampGen {
+    fn init(&mut self) {
+        let microseconds = esp_hal::time::now().duration_since_epoch().to_micros();
+        self.duration = core::time::Duration::from_micros(microseconds);
+    }
+
+    fn timestamp_sec(&self) -> u64 {
+        self.duration.as_secs()
+    }
+
+    fn timestamp_subsec_micros(&self) -> u32 {
+        self.duration.subsec_micros()
+    }
+}
+
+const NTP_PORT: u16 = 123;
+
+/// The servers tried by default, in order.
+pub const DEFAULT_NTP_SERVERS: &[SocketAddr] = &[
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(185, 83, 169, 27), NTP_PORT)),
+    // time.cloudflare.com
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(162, 159, 200, 1), NTP_PORT)),
+    // time.google.com
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(216, 239, 35, 0), NTP_PORT)),
+];
+
+/// The host resolved by default, see [`get_time_host`].
+pub const DEFAULT_NTP_HOST: &str = "pool.ntp.org";
+
+/// How long to wait for each server to answer before trying the next.
+const NTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Ntp packets are 48 bytes, so this leaves plenty of room for a reply
+/// plus the odd stray packet.
+const NTP_BUFFER_SIZE: usize = 256;
+const NTP_PACKETS: usize = 4;
+
+/// The buffers backing an ntp socket from [`bind_ntp_socket`].
+pub struct NtpSocketBuffers {
+    rx_meta: [PacketMetadata; NTP_PACKETS],
+    rx_buffer: [u8; NTP_BUFFER_SIZE],
+    tx_meta: [PacketMetadata; NTP_PACKETS],
+    tx_buffer: [u8; NTP_BUFFER_SIZE],
+}
+
+impl NtpSocketBuffers {
+    pub const fn new() -> Self {
+        Self {
+            rx_meta: [PacketMetadata::EMPTY; NTP_PACKETS],
+            rx_buffer: [0; NTP_BUFFER_SIZE],
+            tx_meta: [PacketMetadata::EMPTY; NTP_PACKETS],
+            tx_buffer: [0; NTP_BUFFER_SIZE],
+        }
+    }
+}
+
+impl Default for NtpSocketBuffers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Create a udp socket on `stack` bound to an ephemeral local port,
+/// ready to pass to [`get_time`].
+pub fn bind_ntp_socket<'a, D: Driver>(
+    stack: &'a Stack<D>,
+    buffers: &'a mut NtpSocketBuffers,
+) -> Result<UdpSocket<'a>, BindError> {
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut buffers.rx_meta,
+        &mut buffers.rx_buffer,
+        &mut buffers.tx_meta,
+        &mut buffers.tx_buffer,
+    );
+
+    // port 0 lets the stack pick a free ephemeral port
+    socket.bind(0).inspect_err(|e| {
+        defmt::error!("failed to bind ntp socket: {}", e);
+    })?;
+
+    Ok(socket)
+}
+
+/// Borrows the socket, so that it can be reused for each server.
+struct EspWifiUdpSocket<'a, 's> {
+    socket: &'a UdpSocket<'s>,
+}
+
+impl<'a, 's> EspWifiUdpSocket<'a, 's> {
+    fn new(socket: &'a UdpSocket<'s>) -> Self {
+        Self { socket }
+    }
+}
+
+impl sntpc::async_impl::NtpUdpSocket for EspWifiUdpSocket<'_, '_> {
+    async fn send_to<T: ToSocketAddrs + Send>(&self, buf: &[u8], addr: T) -> sntpc::Result<usize> {
+        let addrs = addr.to_socket_addrs().unwrap().next().unwrap();
+        let port = addrs.port();
+        let IpAddr::V4(addr) = addrs.ip() else {
+            panic!("we do not support ipv6");
+        };
+        let [a, b, c, d] = addr.octets();
+        self.socket
+            .send_to(
+                buf,
+                (
+                    smoltcp::wire::IpAddress::from(smoltcp::wire::Ipv4Address::new(a, b, c, d)),
+                    port,
+                ),
+            )
+            .await
+            .map_err(|e| {
+                defmt::error!("error during time send: {}", e);
+                sntpc::Error::Network
+            })?;
+        Ok(buf.len())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> sntpc::Result<(usize, SocketAddr)> {
+        self.socket
+            .recv_from(buf)
+            .await
+            .map(|(bytes, meta)| {
+                let IpAddress::Ipv4(smoltcp::wire::Ipv4Address([a, b, c, d])) = meta.endpoint.addr;
+                (
+                    bytes,
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), meta.endpoint.port),
+                )
+            })
+            .map_err(|e| {
+                defmt::error!("error during time recv: {}", e);
+                sntpc::Error::Network
+            })
+    }
+}
+
+impl core::fmt::Debug for EspWifiUdpSocket<'_, '_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "EspWifiUpdSocket")
+    }
+}
+
+/// Ask each of `servers` for the time in order, such as
+/// [`DEFAULT_NTP_SERVERS`], returning the first answer or `None` if none
+/// of them answer.
+pub async fn get_time(socket: &UdpSocket<'_>, servers: &[SocketAddr]) -> Option<NtpResult> {
+    for server in servers {
+        let octets = match server.ip() {
+            IpAddr::V4(ip) => ip.octets(),
+            IpAddr::V6(_) => {
+                defmt::warn!("skipping ipv6 ntp server");
+                continue;
+            }
+        };
+
+        match with_timeout(NTP_TIMEOUT, get_time_from(socket, *server)).await {
+            Ok(Some(result)) => {
+                defmt::info!("got time from ntp server {}", octets);
+                return Some(result);
+            }
+            Ok(None) => defmt::warn!("ntp server {} failed", octets),
+            Err(TimeoutError) => defmt::warn!("ntp server {} timed out", octets),
+        }
+    }
+
+    defmt::error!("no ntp server answered");
+    None
+}
+
+/// Resolve `host`, such as [`DEFAULT_NTP_HOST`], with the stack's dns
+/// socket and ask each of its addresses for the time in turn.
+///
+/// Fails with [`sntpc::Error::AddressResolve`] if the host can't be
+/// resolved, or [`sntpc::Error::Network`] if none of its addresses answer.
+pub async fn get_time_host<D: Driver>(
+    stack: &Stack<D>,
+    socket: &UdpSocket<'_>,
+    host: &str,
+) -> Result<NtpResult, sntpc::Error> {
+    let addresses = stack.dns_query(host, DnsQueryType::A).await.map_err(|e| {
+        defmt::error!("failed to resolve ntp host {}: {}", host, e);
+        sntpc::Error::AddressResolve
+    })?;
+
+    let servers = addresses
+        .iter()
+        .filter_map(|address| match address {
+            IpAddress::Ipv4(smoltcp::wire::Ipv4Address([a, b, c, d])) => Some(SocketAddr::V4(
+                SocketAddrV4::new(Ipv4Addr::new(*a, *b, *c, *d), NTP_PORT),
+            )),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        })
+        .collect::<heapless::Vec<_, 4>>();
+
+    if servers.is_empty() {
+        defmt::error!("ntp host {} has no ipv4 addresses", host);
+        return Err(sntpc::Error::AddressResolve);
+    }
+
+    get_time(socket, &servers)
+        .await
+        .ok_or(sntpc::Error::Network)
+}
+
+/// Get the time from [`DEFAULT_NTP_HOST`], falling back to
+/// [`DEFAULT_NTP_SERVERS`] if the pool can't be reached.
+pub async fn get_default_time<D: Driver>(
+    stack: &Stack<D>,
+    socket: &UdpSocket<'_>,
+) -> Option<NtpResult> {
+    match get_time_host(stack, socket, DEFAULT_NTP_HOST).await {
+        Ok(result) => Some(result),
+        Err(_) => get_time(socket, DEFAULT_NTP_SERVERS).await,
+    }
+}
+
+/// Like [`get_default_time`], but tries up to `attempts` times with a fresh
+/// socket each time, doubling the delay between attempts from `base_delay`.
+pub async fn get_time_with_retry<D: Driver>(
+    stack: &Stack<D>,
+    attempts: u32,
+    base_delay: Duration,
+) -> Option<NtpResult> {
+    let mut delay = base_delay;
+
+    for attempt in 1..=attempts {
+        let mut buffers = NtpSocketBuffers::new();
+        if let Ok(socket) = bind_ntp_socket(stack, &mut buffers) {
+            if let Some(result) = get_default_time(stack, &socket).await {
+                return Some(result);
+            }
+        }
+
+        if attempt < attempts {
+            defmt::warn!(
+                "time sync attempt {} of {} failed, retrying in {}ms",
+                attempt,
+                attempts,
+                delay.as_millis()
+            );
+            Timer::after(delay).await;
+            delay = delay * 2;
+        }
+    }
+
+    defmt::error!("time sync failed after {} attempts", attempts);
+    None
+}
+
+/// Sync `global_time` over ntp, retrying as in [`get_time_with_retry`].
+///
+/// Returns whether the time was synced.
+pub async fn sync_time_with_retry<D: Driver>(
+    stack: &Stack<D>,
+    global_time: &GlobalTime,
+    attempts: u32,
+    base_delay: Duration,
+) -> bool {
+    match get_time_with_retry(stack, attempts, base_delay).await {
+        Some(result) => {
+            global_time.sync(&result);
+            true
+        }
+        None => false,
+    }
+}
+
+async fn get_time_from(socket: &UdpSocket<'_>, server: SocketAddr) -> Option<NtpResult> {
+    let socket = EspWifiUdpSocket::new(socket);
+
+    let context = NtpContext::new(StdTimestampGen::default());
+    sntpc::async_impl::get_time(server, socket, context)
+        .await
+        .inspect_err(|e| {
+            defmt::error!(
+                "failed to get time {}",
+                match e {
+                    sntpc::Error::IncorrectOriginTimestamp => "incorrect origin",
+                    sntpc::Error::IncorrectMode => "incorrect mode",
+                    sntpc::Error::IncorrectLeapIndicator => "incorrect leap",
+                    sntpc::Error::IncorrectResponseVersion => "incorrect response",
+                    sntpc::Error::IncorrectStratumHeaders => "incorrect stratum",
+                    sntpc::Error::IncorrectPayload => "incorrect payload",
+                    sntpc::Error::Network => "network",
+                    sntpc::Error::AddressResolve => "address resolve",
+                    sntpc::Error::ResponseAddressMismatch => "response mismatch",
+                    _ => "unknown",
+                }
+            );
+        })
+        .ok()
+}
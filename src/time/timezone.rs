@@ -0,0 +1,186 @@
+//! Timezones with daylight saving rules.
+
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset, Weekday};
+
+/// Which occurrence of a weekday in the month a transition happens on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Week {
+    First,
+    Second,
+    Third,
+    Fourth,
+    Last,
+}
+
+/// A yearly transition into or out of daylight saving time, such as
+/// "the last Sunday of March at 02:00".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransitionRule {
+    pub month: Month,
+    pub week: Week,
+    pub weekday: Weekday,
+    /// The local hour of the transition, in the offset that applies
+    /// just before it.
+    pub hour: u8,
+}
+
+impl TransitionRule {
+    /// When this transition happens in `year`, given the offset in effect
+    /// just before it.
+    fn datetime(&self, year: i32, offset: UtcOffset) -> Option<OffsetDateTime> {
+        let first = Date::from_calendar_date(year, self.month, 1).ok()?;
+        let target = self.weekday.number_days_from_monday();
+
+        let day = match self.week {
+            Week::Last => {
+                let last = time::util::days_in_year_month(year, self.month);
+                let last_weekday = first.replace_day(last).ok()?.weekday();
+                last - (last_weekday.number_days_from_monday() + 7 - target) % 7
+            }
+            week => {
+                let nth = week as u8;
+                let first_weekday = first.weekday().number_days_from_monday();
+                1 + (target + 7 - first_weekday) % 7 + 7 * nth
+            }
+        };
+
+        let date = first.replace_day(day).ok()?;
+        let time = Time::from_hms(self.hour, 0, 0).ok()?;
+        Some(PrimitiveDateTime::new(date, time).assume_offset(offset))
+    }
+}
+
+/// A timezone, with an optional daylight saving period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timezone {
+    /// The offset outside of daylight saving time.
+    pub std_offset: UtcOffset,
+    /// The offset during daylight saving time.
+    pub dst_offset: UtcOffset,
+    /// When daylight saving starts and ends, or `None` for a fixed offset.
+    pub rules: Option<(TransitionRule, TransitionRule)>,
+}
+
+/// A set of daylight saving rules, to pair with a base offset at runtime,
+/// see [`DstRule::timezone`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DstRule {
+    /// No daylight saving, the offset is fixed.
+    #[default]
+    None,
+    /// The european rules, see [`Timezone::eu`].
+    Eu,
+    /// The north american rules, see [`Timezone::us`].
+    Us,
+}
+
+impl DstRule {
+    /// The timezone following these rules, with `std_offset` outside of
+    /// daylight saving time.
+    pub const fn timezone(self, std_offset: UtcOffset) -> Timezone {
+        match self {
+            DstRule::None => Timezone::fixed(std_offset),
+            DstRule::Eu => Timezone::eu(std_offset),
+            DstRule::Us => Timezone::us(std_offset),
+        }
+    }
+}
+
+impl From<UtcOffset> for Timezone {
+    fn from(offset: UtcOffset) -> Self {
+        Self::fixed(offset)
+    }
+}
+
+impl Timezone {
+    /// A timezone that is always at `offset`.
+    pub const fn fixed(offset: UtcOffset) -> Self {
+        Self {
+            std_offset: offset,
+            dst_offset: offset,
+            rules: None,
+        }
+    }
+
+    /// A european timezone, which moves forward an hour from the last
+    /// Sunday in March to the last Sunday in October, at 01:00 UTC.
+    pub const fn eu(std_offset: UtcOffset) -> Self {
+        let dst_offset = plus_hour(std_offset);
+        Self {
+            std_offset,
+            dst_offset,
+            rules: Some((
+                TransitionRule {
+                    month: Month::March,
+                    week: Week::Last,
+                    weekday: Weekday::Sunday,
+                    hour: (1 + std_offset.whole_hours()) as u8,
+                },
+                TransitionRule {
+                    month: Month::October,
+                    week: Week::Last,
+                    weekday: Weekday::Sunday,
+                    hour: (1 + dst_offset.whole_hours()) as u8,
+                },
+            )),
+        }
+    }
+
+    /// A north american timezone, which moves forward an hour from the
+    /// second Sunday in March to the first Sunday in November, at 02:00.
+    pub const fn us(std_offset: UtcOffset) -> Self {
+        Self {
+            std_offset,
+            dst_offset: plus_hour(std_offset),
+            rules: Some((
+                TransitionRule {
+                    month: Month::March,
+                    week: Week::Second,
+                    weekday: Weekday::Sunday,
+                    hour: 2,
+                },
+                TransitionRule {
+                    month: Month::November,
+                    week: Week::First,
+                    weekday: Weekday::Sunday,
+                    hour: 2,
+                },
+            )),
+        }
+    }
+
+    /// The offset in effect at `utc`.
+    pub fn offset_at(&self, utc: OffsetDateTime) -> UtcOffset {
+        let Some((start_rule, end_rule)) = self.rules else {
+            return self.std_offset;
+        };
+
+        let year = utc.to_offset(self.std_offset).year();
+        let (Some(start), Some(end)) = (
+            start_rule.datetime(year, self.std_offset),
+            end_rule.datetime(year, self.dst_offset),
+        ) else {
+            return self.std_offset;
+        };
+
+        // in the southern hemisphere daylight saving spans the new year
+        let dst = match start < end {
+            true => utc >= start && utc < end,
+            false => utc >= start || utc < end,
+        };
+
+        match dst {
+            true => self.dst_offset,
+            false => self.std_offset,
+        }
+    }
+}
+
+/// An offset one hour ahead of `offset`.
+const fn plus_hour(offset: UtcOffset) -> UtcOffset {
+    let (hours, minutes, seconds) = offset.as_hms();
+    match UtcOffset::from_hms(hours + 1, minutes, seconds) {
+        Ok(v) => v,
+        Err(_) => panic!("Bad value"),
+    }
+}
@@ -1,26 +1,605 @@
-use embedded_fonts::BdfTextStyle;
-use embedded_graphics::{mono_font::MonoTextStyleBuilder, prelude::*, text::Text};
+use embedded_fonts::{BdfFont, BdfTextStyle};
+use embedded_graphics::{
+    mono_font::{MonoFont, MonoTextStyleBuilder},
+    pixelcolor::Gray2,
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+    text::Text,
+};
 use epd_waveshare::{epd1in54::Display1in54, prelude::*};
-use esp_hal::{gpio::GpioPin, peripherals::ADC1, prelude::*};
+use esp_hal::{gpio::GpioPin, prelude::*};
 use futures::{pin_mut, StreamExt};
 
-use core::cell::RefCell;
-use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
-use embassy_sync::blocking_mutex::{raw::NoopRawMutex, Mutex};
-use epd_waveshare::epd1in54_v2::Epd1in54;
+use core::cell::{Cell, RefCell};
+use core::convert::Infallible;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_embedded_hal::shared_bus::{blocking::spi::SpiDevice, SpiDeviceError};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::{
+    raw::{CriticalSectionRawMutex, NoopRawMutex},
+    Mutex,
+};
+use embassy_time::{Duration, Instant};
+use epd_waveshare::epd1in54_v2::{Epd1in54, WIDTH};
 use esp_hal::{
     delay::Delay,
-    gpio::{Input, Level, Output, Pull},
+    gpio::{ErasedPin, Input, Level, Output, Pull},
+    peripheral::Peripheral,
     peripherals::SPI2,
-    spi::master::Spi,
+    spi::{master::Spi, FullDuplexMode},
 };
 
-use crate::{BatteryStatusDriver, GlobalTime};
+use crate::display::{Panel, PanelBuffer};
+use crate::grayscale::{Gray4Buffer, GrayscalePanel};
+use crate::{sticky_signal::StickySignal, GlobalTime};
 
-const TIMEZONE: time::UtcOffset = match time::UtcOffset::from_hms(1, 0, 0) {
-    Ok(v) => v,
-    Err(_) => panic!("Bad value"),
-};
+/// Everything that ends up on the face, so that draws with no visual
+/// change can skip waking the panel entirely.
+#[derive(PartialEq)]
+struct Frame {
+    /// The hour and minute, or `None` if the time isn't synced yet.
+    time: Option<(u8, u8)>,
+    /// The day and month, once the time is synced.
+    date: Option<(u8, u8)>,
+    /// The displayed battery percentage, if it has been read yet.
+    battery: Option<u8>,
+    charge_state: crate::ChargeState,
+    temperature: Option<i8>,
+    /// Steps taken today, once the step counter has been read.
+    steps: Option<u32>,
+    alarm: bool,
+    stopwatch: Option<heapless::String<8>>,
+    /// The label, hour and minute of the secondary timezone, if any.
+    secondary: Option<(&'static str, u8, u8)>,
+    /// The wifi signal strength in bars, while connected.
+    wifi_bars: Option<u8>,
+    /// Which way up to draw the face.
+    orientation: crate::accel::Orientation,
+}
+
+/// Where the secondary timezone is drawn, just under the main clock.
+const SECONDARY_POSITION: Point = Point::new(65, 75);
+
+/// Where the syncing indicator is drawn, until the time is known.
+const SYNCING_POSITION: Point = Point::new(65, 130);
+
+/// Where the date is drawn, taking the syncing indicator's place once the
+/// time is known.
+const DATE_POSITION: Point = Point::new(80, 130);
+
+/// The rotation that keeps the face upright when the watch is held in
+/// `orientation`. The panel is square, so the layout fits either way.
+fn display_rotation(orientation: crate::accel::Orientation) -> DisplayRotation {
+    match orientation {
+        crate::accel::Orientation::Normal => DisplayRotation::Rotate0,
+        crate::accel::Orientation::RotatedLeft => DisplayRotation::Rotate90,
+        crate::accel::Orientation::Upside => DisplayRotation::Rotate180,
+        crate::accel::Orientation::RotatedRight => DisplayRotation::Rotate270,
+    }
+}
+
+/// Where the stopwatch is drawn.
+const STOPWATCH_POSITION: Point = Point::new(70, 100);
+
+/// Where today's step count is drawn, above the battery.
+const STEPS_POSITION: Point = Point::new(60, 175);
+/// The rows covered by the stopwatch, as `(y, height)`, which are
+/// refreshed on their own while it is running.
+const STOPWATCH_ROWS: (u32, u32) = (88, 16);
+
+/// How long refreshes with each LUT take, see [`refresh_metrics`].
+static REFRESH_METRICS: Mutex<CriticalSectionRawMutex, Cell<RefreshMetrics>> =
+    Mutex::new(Cell::new(RefreshMetrics::new()));
+
+/// Timings for the refreshes done with a single LUT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefreshTimings {
+    /// How many refreshes have been done.
+    pub count: u32,
+    /// How long the latest refresh took.
+    pub last: Duration,
+    /// How long all the refreshes took together.
+    pub total: Duration,
+}
+
+impl RefreshTimings {
+    const fn new() -> Self {
+        Self {
+            count: 0,
+            last: Duration::from_ticks(0),
+            total: Duration::from_ticks(0),
+        }
+    }
+
+    fn record(&mut self, duration: Duration) {
+        self.count = self.count.saturating_add(1);
+        self.last = duration;
+        self.total += duration;
+    }
+
+    /// The mean refresh duration, or `None` if there have been none.
+    pub fn average(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count)
+    }
+}
+
+impl defmt::Format for RefreshTimings {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "{} frames, last {}ms, avg {}ms",
+            self.count,
+            self.last.as_millis(),
+            self.average().unwrap_or_default().as_millis()
+        )
+    }
+}
+
+/// How long the panel takes to update and refresh, split by LUT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct RefreshMetrics {
+    pub full: RefreshTimings,
+    pub quick: RefreshTimings,
+    /// Refreshes with the 4 gray LUT, see [`FaceConfig::grayscale`].
+    pub grayscale: RefreshTimings,
+}
+
+impl RefreshMetrics {
+    const fn new() -> Self {
+        Self {
+            full: RefreshTimings::new(),
+            quick: RefreshTimings::new(),
+            grayscale: RefreshTimings::new(),
+        }
+    }
+}
+
+/// The refresh timings measured by [`drive_display`] since boot, covering
+/// the frame upload and the refresh itself.
+pub fn refresh_metrics() -> RefreshMetrics {
+    REFRESH_METRICS.lock(|metrics| metrics.get())
+}
+
+/// The LUT the panel refreshed with, for the metrics.
+#[derive(Clone, Copy)]
+enum Waveform {
+    Mono(RefreshLut),
+    Grayscale,
+}
+
+fn record_refresh(waveform: Waveform, duration: Duration) {
+    let metrics = REFRESH_METRICS.lock(|metrics| {
+        let mut updated = metrics.get();
+        match waveform {
+            Waveform::Mono(RefreshLut::Full) => updated.full.record(duration),
+            Waveform::Mono(RefreshLut::Quick) => updated.quick.record(duration),
+            Waveform::Grayscale => updated.grayscale.record(duration),
+        }
+        metrics.set(updated);
+        updated
+    });
+    defmt::info!("refresh took {}ms, {}", duration.as_millis(), metrics);
+}
+
+/// How many times [`setup_epd`] tries to initialise the display.
+pub const EPD_INIT_ATTEMPTS: u32 = 3;
+
+/// How long to hold the display in and out of reset between attempts, in ms.
+const EPD_RESET_MS: u32 = 10;
+
+/// How often to poll the busy pin while waiting for the panel, in µs.
+pub(crate) const BUSY_POLL_US: u32 = 1_000;
+
+/// Set when the panel has stayed busy for longer than
+/// [`DisplayConfig::busy_timeout`], so the display task can reset it.
+static BUSY_TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+/// Settings for driving the e-paper panel.
+#[derive(Clone, Copy)]
+pub struct DisplayConfig {
+    /// How long to wait for the panel to finish an operation before
+    /// giving up and resetting it. A full refresh takes around 2s.
+    pub busy_timeout: Duration,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            busy_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// The panel's busy pin, which stops reporting busy once the panel has
+/// been busy for longer than the timeout.
+///
+/// The driver polls this until the panel is idle, so a stuck panel would
+/// otherwise hang the display task forever. On timeout the pin returns an
+/// error, which the driver treats as idle, and [`BUSY_TIMED_OUT`] is set.
+pub struct BusyPin<P> {
+    pin: P,
+    timeout: Duration,
+    busy_since: Option<Instant>,
+}
+
+impl<P> BusyPin<P> {
+    pub(crate) fn new(pin: P, timeout: Duration) -> Self {
+        Self {
+            pin,
+            timeout,
+            busy_since: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BusyError<E> {
+    Pin(E),
+    TimedOut,
+}
+
+impl<E: core::fmt::Debug> embedded_hal::digital::Error for BusyError<E> {
+    fn kind(&self) -> embedded_hal::digital::ErrorKind {
+        embedded_hal::digital::ErrorKind::Other
+    }
+}
+
+impl<P: embedded_hal::digital::ErrorType> embedded_hal::digital::ErrorType for BusyPin<P> {
+    type Error = BusyError<P::Error>;
+}
+
+impl<P: embedded_hal::digital::InputPin> embedded_hal::digital::InputPin for BusyPin<P> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        if !self.pin.is_high().map_err(BusyError::Pin)? {
+            self.busy_since = None;
+            return Ok(false);
+        }
+
+        let busy_since = *self.busy_since.get_or_insert_with(Instant::now);
+        if busy_since.elapsed() > self.timeout {
+            defmt::error!(
+                "display busy for over {}ms, giving up",
+                self.timeout.as_millis()
+            );
+            self.busy_since = None;
+            BUSY_TIMED_OUT.store(true, Ordering::Relaxed);
+            return Err(BusyError::TimedOut);
+        }
+
+        Ok(true)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+/// Pending out-of-band redraw requests for the display.
+static REDRAW: StickySignal<CriticalSectionRawMutex, (), 4> = StickySignal::new_with_name("redraw");
+
+/// Ask the display to redraw as soon as possible, rather than on the next minute.
+///
+/// Requests made while the display is busy are coalesced into a single redraw.
+pub fn request_redraw() {
+    REDRAW.signal(());
+}
+
+/// Whether the panel is asleep, so it is safe to cut its power.
+static DISPLAY_ASLEEP: StickySignal<CriticalSectionRawMutex, bool, 2> =
+    StickySignal::new_with_name("display_asleep");
+
+/// Wait until the display isn't drawing and the panel is asleep.
+///
+/// The panel starts asleep, so this returns straight away before the
+/// first draw.
+pub(crate) async fn wait_display_asleep() {
+    if DISPLAY_ASLEEP.peek().unwrap_or(true) {
+        return;
+    }
+    DISPLAY_ASLEEP
+        .wait_for("display asleep", |asleep| asleep.then_some(()))
+        .await
+}
+
+/// Completes when a redraw has been requested, consuming any pending requests.
+async fn redraw_requested() {
+    if REDRAW.try_take().is_none() {
+        REDRAW.wait("redraw requested").await;
+        REDRAW.reset();
+    }
+}
+
+/// The look of the watch face.
+#[derive(Clone, Copy)]
+pub struct FaceConfig {
+    /// The font used for the big clock digits.
+    pub clock_font: &'static BdfFont<'static>,
+    /// The font used for small text, such as the battery status.
+    pub text_font: &'static MonoFont<'static>,
+    /// Draw the face with the 4 gray waveform, adding a gray battery gauge
+    /// and showing the date in gray. Otherwise the face is black and white.
+    ///
+    /// Every draw is then a full refresh with the slower grayscale LUT,
+    /// about 2.3s, including the stopwatch updates that would otherwise
+    /// only refresh its rows. The measured times are in [`refresh_metrics`].
+    pub grayscale: bool,
+}
+
+impl Default for FaceConfig {
+    fn default() -> Self {
+        Self {
+            clock_font: &crate::fonts::space_mono::FONT_SPACEM_ITALICN_ITALIC_REGULAR,
+            text_font: &embedded_graphics::mono_font::ascii::FONT_7X14_BOLD,
+            grayscale: false,
+        }
+    }
+}
+
+/// Where the battery gauge is drawn in grayscale mode.
+const BATTERY_GAUGE: Rectangle = Rectangle::new(Point::new(10, 184), Size::new(40, 12));
+
+/// The shades of the gray parts of the face in grayscale mode.
+const GAUGE_SHADE: Gray2 = Gray2::new(2);
+const DATE_SHADE: Gray2 = Gray2::new(1);
+
+/// Where the wifi signal bars are drawn, from the bottom left of the first bar.
+const SIGNAL_BARS_POSITION: Point = Point::new(175, 20);
+
+/// Draw four bars of increasing height, outlining the ones above `bars`.
+fn draw_signal_bars<D: DrawTarget<Color = Color>>(
+    display: &mut D,
+    bars: u8,
+) -> Result<(), D::Error> {
+    for i in 0..4 {
+        let height = 3 + 3 * i;
+        let bar = Rectangle::new(
+            SIGNAL_BARS_POSITION + Point::new(5 * i as i32, -(height as i32)),
+            Size::new(3, height),
+        );
+        let style = match u32::from(bars) > i {
+            true => PrimitiveStyle::with_fill(Color::Black),
+            false => PrimitiveStyle::with_stroke(Color::Black, 1),
+        };
+        bar.into_styled(style).draw(display)?;
+    }
+    Ok(())
+}
+
+/// Draw a battery outline with a gray fill for the remaining charge.
+fn draw_battery_gauge<D: DrawTarget<Color = Gray2>>(
+    display: &mut D,
+    percentage: u8,
+) -> Result<(), D::Error> {
+    BATTERY_GAUGE
+        .into_styled(PrimitiveStyle::with_stroke(Gray2::BLACK, 1))
+        .draw(display)?;
+
+    let inner = BATTERY_GAUGE.offset(-2);
+    let width = inner.size.width * u32::from(percentage.min(100)) / 100;
+    Rectangle::new(inner.top_left, Size::new(width, inner.size.height))
+        .into_styled(PrimitiveStyle::with_fill(GAUGE_SHADE))
+        .draw(display)
+}
+
+/// Draw the day and month as `dd/mm`.
+fn draw_date<D: DrawTarget>(
+    display: &mut D,
+    (day, month): (u8, u8),
+    font: &MonoFont,
+    color: D::Color,
+) -> Result<(), D::Error> {
+    let mut string = heapless::String::<8>::new();
+    for (value, separator) in [(day, "/"), (month, "")] {
+        if value < 10 {
+            let _ = ufmt::uwrite!(string, "0{}{}", value, separator);
+        } else {
+            let _ = ufmt::uwrite!(string, "{}{}", value, separator);
+        }
+    }
+    let style = MonoTextStyleBuilder::new()
+        .font(font)
+        .text_color(color)
+        .build();
+    Text::new(&string, DATE_POSITION, style).draw(display)?;
+    Ok(())
+}
+
+/// Pending requests to clear ghosting on the next draw.
+static CLEAR_GHOSTING: StickySignal<CriticalSectionRawMutex, (), 1> =
+    StickySignal::new_with_name("clear_ghosting");
+
+/// Ask the display to clear ghosting before its next draw, see [`clear_ghosting`].
+pub fn request_clear_ghosting() {
+    CLEAR_GHOSTING.signal(());
+    request_redraw();
+}
+
+/// Flush the panel between full black and full white frames to remove
+/// the ghosting that builds up from partial refreshes.
+///
+/// This takes a few seconds, so run it sparingly, for example once a day
+/// or on demand when the image looks muddy. The panel is always left white
+/// with the full LUT selected, so the next draw starts from a clean state.
+pub fn clear_ghosting<SPI, BUSY, DC, RST, DELAY, EPD>(
+    epd: &mut EPD,
+    spi: &mut SPI,
+    delay: &mut DELAY,
+) -> Result<(), SPI::Error>
+where
+    SPI: embedded_hal::spi::SpiDevice,
+    BUSY: embedded_hal::digital::InputPin,
+    DC: embedded_hal::digital::OutputPin,
+    RST: embedded_hal::digital::OutputPin,
+    DELAY: embedded_hal::delay::DelayNs,
+    EPD: WaveshareDisplay<SPI, BUSY, DC, RST, DELAY>,
+{
+    const CYCLES: usize = 3;
+
+    epd.set_lut(spi, delay, Some(RefreshLut::Full))?;
+    for _ in 0..CYCLES {
+        for color in [Color::Black, Color::White] {
+            epd.set_background_color(color);
+            epd.clear_frame(spi, delay)?;
+            epd.display_frame(spi, delay)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The spi bus the display is on.
+pub type EpdBus = Mutex<NoopRawMutex, RefCell<Spi<'static, SPI2, FullDuplexMode>>>;
+
+/// The display's device on an [`EpdBus`].
+pub type EpdSpi<'a> =
+    SpiDevice<'a, NoopRawMutex, Spi<'static, SPI2, FullDuplexMode>, Output<'static, ErasedPin>>;
+
+/// The display driver, as set up by [`setup_epd`].
+pub type Epd<'a> = Epd1in54<
+    EpdSpi<'a>,
+    BusyPin<Input<'static, ErasedPin>>,
+    Output<'static, ErasedPin>,
+    Output<'static, ErasedPin>,
+    Delay,
+>;
+
+/// Set up the spi bus for the display, see [`setup_epd`].
+pub fn epd_bus(spi: SPI2, sck: GpioPin<47>, miso: GpioPin<46>, mosi: GpioPin<48>) -> EpdBus {
+    let spi = Spi::new(spi, 2.MHz(), esp_hal::spi::SpiMode::Mode0)
+        .with_sck(sck)
+        .with_miso(miso)
+        .with_mosi(mosi);
+
+    Mutex::new(RefCell::new(spi))
+}
+
+/// Set up the display on `bus`, returning the driver and the spi device
+/// to pass to it.
+///
+/// If the panel fails to initialise, it is reset and tried again, up to
+/// [`EPD_INIT_ATTEMPTS`] times, before giving up with the last error.
+///
+/// The bus is passed in rather than created here since the device
+/// borrows it, see [`epd_bus`].
+pub fn setup_epd<'a>(
+    bus: &'a EpdBus,
+    mut cs: GpioPin<33>,
+    mut dc: GpioPin<34>,
+    mut reset: GpioPin<35>,
+    mut busy: GpioPin<36>,
+    delay: &mut Delay,
+    config: DisplayConfig,
+) -> Result<(Epd<'a>, EpdSpi<'a>), SpiDeviceError<esp_hal::spi::Error, Infallible>> {
+    let mut attempt = 1;
+    loop {
+        // SAFETY: the drivers from a failed attempt are dropped along with
+        // their pins, so each pin only ever has one driver at a time.
+        let (cs, dc, reset_pin, busy) = unsafe {
+            (
+                cs.clone_unchecked(),
+                dc.clone_unchecked(),
+                reset.clone_unchecked(),
+                busy.clone_unchecked(),
+            )
+        };
+
+        let pin_edp_dc = Output::new(dc, Level::Low);
+        let pin_edp_reset = Output::new(reset_pin, Level::Low);
+        let pin_edp_busy = BusyPin::new(Input::new(busy, Pull::Up), config.busy_timeout);
+
+        let mut spi = SpiDevice::new(bus, Output::new(cs, Level::Low));
+        let error = match Epd1in54::new(
+            &mut spi,
+            pin_edp_busy,
+            pin_edp_dc,
+            pin_edp_reset,
+            delay,
+            Some(BUSY_POLL_US),
+        ) {
+            Ok(epd) => return Ok((epd, spi)),
+            Err(error) => error,
+        };
+
+        defmt::warn!(
+            "display init attempt {} of {} failed",
+            attempt,
+            EPD_INIT_ATTEMPTS
+        );
+        if attempt >= EPD_INIT_ATTEMPTS {
+            defmt::error!("giving up on the display");
+            return Err(error);
+        }
+        attempt += 1;
+
+        // toggle the reset line to get the panel back to a known state
+        // SAFETY: as above, the failed driver has already been dropped
+        let mut pin_edp_reset = Output::new(unsafe { reset.clone_unchecked() }, Level::Low);
+        delay.delay_millis(EPD_RESET_MS);
+        pin_edp_reset.set_high();
+        delay.delay_millis(EPD_RESET_MS);
+    }
+}
+
+/// Show a single line of text on an otherwise blank display, for example
+/// when the watch face isn't running.
+pub fn show_message<SPI, BUSY, DC, RST, DELAY, EPD>(
+    epd: &mut EPD,
+    spi: &mut SPI,
+    delay: &mut DELAY,
+    message: &str,
+) -> Result<(), SPI::Error>
+where
+    SPI: embedded_hal::spi::SpiDevice,
+    BUSY: embedded_hal::digital::InputPin,
+    DC: embedded_hal::digital::OutputPin,
+    RST: embedded_hal::digital::OutputPin,
+    DELAY: embedded_hal::delay::DelayNs,
+    EPD: Panel<SPI, BUSY, DC, RST, DELAY>,
+{
+    let style = MonoTextStyleBuilder::new()
+        .font(FaceConfig::default().text_font)
+        .text_color(Color::Black)
+        .build();
+
+    let mut display = EPD::Buffer::default();
+    let _ = display.clear(Color::White);
+    let middle = display.size().height as i32 / 2;
+    let _ = Text::new(message, Point::new(20, middle), style).draw(&mut display);
+
+    epd.update_frame(spi, display.bytes(), delay)?;
+    epd.display_frame(spi, delay)?;
+    epd.sleep(spi, delay)
+}
+
+/// A simple pattern for checking the panel works: a border, both
+/// diagonals and a circle in the middle, scaled to fit the buffer.
+pub(crate) fn test_pattern<D: PanelBuffer>() -> D {
+    let mut display = D::default();
+    let _ = display.clear(Color::White);
+
+    let style = PrimitiveStyleBuilder::new()
+        .stroke_color(Color::Black)
+        .stroke_width(1)
+        .build();
+    let bounds = display.bounding_box();
+    let corner = bounds.bottom_right().unwrap_or_default();
+
+    let _ = bounds.into_styled(style).draw(&mut display);
+    let _ = Line::new(Point::zero(), corner)
+        .into_styled(style)
+        .draw(&mut display);
+    let _ = Line::new(Point::new(corner.x, 0), Point::new(0, corner.y))
+        .into_styled(style)
+        .draw(&mut display);
+    let _ = Circle::with_center(
+        bounds.center(),
+        bounds.size.width.min(bounds.size.height) / 2,
+    )
+    .into_styled(PrimitiveStyle::with_fill(Color::Black))
+    .draw(&mut display);
+
+    display
+}
 
 #[embassy_executor::task]
 pub async fn drive_display(
@@ -29,37 +608,25 @@ pub async fn drive_display(
     miso: GpioPin<46>,
     mosi: GpioPin<48>,
     cs: GpioPin<33>,
-    dc: GpioPin<34>,
+    mut dc: GpioPin<34>,
     reset: GpioPin<35>,
-    busy: GpioPin<36>,
+    mut busy: GpioPin<36>,
     global_time: GlobalTime,
     mut delay: Delay,
-    battery_adc: GpioPin<9>,
-    charge_pin: GpioPin<10>,
-    adc: ADC1,
+    face: FaceConfig,
+    config: DisplayConfig,
 ) {
-    let pin_spi_edp_cs = Output::new(cs, Level::Low);
-    let pin_edp_dc = Output::new(dc, Level::Low);
-    let pin_edp_reset = Output::new(reset, Level::Low);
-    let pin_edp_busy = Input::new(busy, Pull::Up);
-
-    let spi = Spi::new(spi, 2.MHz(), esp_hal::spi::SpiMode::Mode0)
-        .with_sck(sck)
-        .with_miso(miso)
-        .with_mosi(mosi);
-
-    let spi = Mutex::<NoopRawMutex, _>::new(RefCell::new(spi));
-
-    let mut spi = SpiDevice::new(&spi, pin_spi_edp_cs);
-    let mut epd = Epd1in54::new(
-        &mut spi,
-        pin_edp_busy,
-        pin_edp_dc,
-        pin_edp_reset,
-        &mut delay,
-        Some(1_000),
-    )
-    .unwrap();
+    let bus = epd_bus(spi, sck, miso, mosi);
+    // SAFETY: the grayscale panel and the driver are only used one after
+    // the other from this task, never at the same time
+    let mut grayscale = face
+        .grayscale
+        .then(|| unsafe { GrayscalePanel::new(&mut dc, &mut busy, config) });
+    let Ok((mut epd, mut spi)) = setup_epd(&bus, cs, dc, reset, busy, &mut delay, config) else {
+        // the rest of the watch keeps running, just without a face
+        defmt::error!("failed to set up the display");
+        return;
+    };
 
     // every 5 renders we should use the full LUT
     let lut_loop = [
@@ -70,30 +637,40 @@ pub async fn drive_display(
         None,
     ];
 
-    let mut battery = BatteryStatusDriver::new(battery_adc, charge_pin, adc);
+    let mut awake = false;
+    let mut last_frame = None;
+    // the lut stays loaded between draws, so remember it for the metrics
+    let mut current_lut = RefreshLut::Full;
 
     loop {
         defmt::info!("starting draw loop");
 
         // render now, and every 60 seconds
-        let updates =
+        let ticks =
             futures::stream::once(async { global_time.get_time() }).chain(global_time.minutes());
+        pin_mut!(ticks);
 
-        let lut_loop = futures::stream::iter(lut_loop).cycle();
+        // as well as whenever a redraw is requested
+        let updates = futures::stream::unfold(ticks, |mut ticks| async move {
+            match select(ticks.next(), redraw_requested()).await {
+                Either::First(update) => update.map(|update| ((update, false), ticks)),
+                Either::Second(()) => {
+                    defmt::info!("redraw requested");
+                    Some(((global_time.get_time(), true), ticks))
+                }
+            }
+        });
+        pin_mut!(updates);
 
-        let draw_patterns = updates.zip(lut_loop);
-        pin_mut!(draw_patterns);
+        // only scheduled draws advance the lut loop
+        let mut luts = lut_loop.into_iter().cycle();
 
-        while let Some((update, lut)) = draw_patterns.next().await {
-            defmt::info!("drawing");
-            let update = i64::try_from(update / 1_000_000).unwrap();
-            let date = time::OffsetDateTime::from_unix_timestamp(update)
-                .unwrap()
-                .to_offset(TIMEZONE);
+        while let Some((update, requested)) = updates.next().await {
+            let date = global_time.local_time(update);
 
             defmt::info!(
                 "{} -> date is {}/{}/{} {} {}",
-                update,
+                update / 1_000_000,
                 date.year(),
                 u8::from(date.month()),
                 date.day(),
@@ -101,34 +678,94 @@ pub async fn drive_display(
                 date.minute()
             );
 
-            epd.wake_up(&mut spi, &mut delay).unwrap();
+            // before the first sync the time is just the uptime, so don't show it
+            let synced = global_time.time_since_sync().is_some();
+
+            let frame = Frame {
+                time: synced.then(|| (date.hour(), date.minute())),
+                date: synced.then(|| (date.day(), u8::from(date.month()))),
+                battery: crate::BATTERY_PERCENTAGE.peek(),
+                charge_state: crate::CHARGE_STATE.peek().unwrap_or_default(),
+                temperature: crate::accel::ACCEL_TEMPERATURE.peek(),
+                steps: crate::accel::DAILY_STEPS.peek(),
+                alarm: crate::alarm::ALARM_RINGING.peek().unwrap_or_default(),
+                stopwatch: crate::stopwatch::elapsed().map(crate::stopwatch::format_elapsed),
+                secondary: global_time
+                    .secondary_time(update)
+                    .filter(|_| synced)
+                    .map(|(label, date)| (label, date.hour(), date.minute())),
+                #[cfg(feature = "wifi")]
+                wifi_bars: crate::WIFI_RSSI.peek().flatten().map(crate::signal_bars),
+                #[cfg(not(feature = "wifi"))]
+                wifi_bars: None,
+                orientation: crate::accel::ORIENTATION.peek().unwrap_or_default(),
+            };
+
+            if last_frame.as_ref() == Some(&frame) && CLEAR_GHOSTING.peek().is_none() {
+                defmt::info!("nothing changed, skipping draw");
+                continue;
+            }
+
+            // turning the face redraws everything, so needs a full refresh
+            let rotated = last_frame
+                .as_ref()
+                .is_some_and(|last| last.orientation != frame.orientation);
+
+            // while the stopwatch is running, requested draws only refresh its
+            // rows. these are physical rows, so only line up when unrotated.
+            // the grayscale LUT can only refresh the whole panel
+            let partial = requested
+                && grayscale.is_none()
+                && crate::stopwatch::is_running()
+                && frame.orientation == crate::accel::Orientation::Normal
+                && !rotated;
+            let lut = match (partial, rotated) {
+                (true, _) => Some(RefreshLut::Quick),
+                (false, true) => Some(RefreshLut::Full),
+                (false, false) => luts.next().flatten(),
+            };
+
+            defmt::info!("drawing (partial: {})", partial);
+
+            if !awake {
+                DISPLAY_ASLEEP.signal(false);
+                epd.wake_up(&mut spi, &mut delay).unwrap();
+                awake = true;
+            }
 
-            if let Some(lut) = lut {
+            if CLEAR_GHOSTING.try_take().is_some() {
+                defmt::info!("clearing ghosting");
+                clear_ghosting(&mut epd, &mut spi, &mut delay).unwrap();
+                current_lut = RefreshLut::Full;
+            }
+
+            // the grayscale panel loads its own lut for every draw
+            if let Some(lut) = lut.filter(|_| grayscale.is_none()) {
                 epd.set_lut(&mut spi, &mut delay, Some(lut)).unwrap();
+                current_lut = lut;
             };
 
-            let style = BdfTextStyle::new(
-                &crate::fonts::space_mono::FONT_SPACEM_ITALICN_ITALIC_REGULAR,
-                Color::Black,
-            );
+            let style = BdfTextStyle::new(face.clock_font, Color::Black);
 
             let battery_style = MonoTextStyleBuilder::new()
-                .font(&embedded_graphics::mono_font::ascii::FONT_7X14_BOLD)
+                .font(face.text_font)
                 .text_color(Color::Black)
                 .build();
 
             // Use display graphics from embedded-graphics
             let display = {
                 let mut display = Display1in54::default();
+                display.set_rotation(display_rotation(frame.orientation));
                 display.clear(Color::White).unwrap();
 
                 {
                     let mut string = heapless::String::<8>::new();
-                    if date.hour() < 10 {
-                        ufmt::uwrite!(string, "0{}", date.hour()).unwrap();
-                    } else {
-                        ufmt::uwrite!(string, "{}", date.hour()).unwrap();
-                    };
+                    match frame.time {
+                        Some((hour, _)) if hour < 10 => ufmt::uwrite!(string, "0{}", hour),
+                        Some((hour, _)) => ufmt::uwrite!(string, "{}", hour),
+                        None => ufmt::uwrite!(string, "--"),
+                    }
+                    .unwrap();
                     let _ = Text::new(&string, Point::new(20, 50), style).draw(&mut display);
                 }
                 {
@@ -136,26 +773,83 @@ pub async fn drive_display(
                 }
                 {
                     let mut string = heapless::String::<8>::new();
-                    if date.minute() < 10 {
-                        ufmt::uwrite!(string, "0{}", date.minute()).unwrap();
-                    } else {
-                        ufmt::uwrite!(string, "{}", date.minute()).unwrap();
-                    };
+                    match frame.time {
+                        Some((_, minute)) if minute < 10 => ufmt::uwrite!(string, "0{}", minute),
+                        Some((_, minute)) => ufmt::uwrite!(string, "{}", minute),
+                        None => ufmt::uwrite!(string, "--"),
+                    }
+                    .unwrap();
                     let _ = Text::new(&string, Point::new(115, 50), style).draw(&mut display);
                 }
 
-                {
-                    let bat = battery.status().await.unwrap();
+                if let Some((label, hour, minute)) = frame.secondary {
+                    let mut string = heapless::String::<16>::new();
+                    let _ = ufmt::uwrite!(string, "{} ", label);
+                    for (value, separator) in [(hour, ":"), (minute, "")] {
+                        if value < 10 {
+                            let _ = ufmt::uwrite!(string, "0{}{}", value, separator);
+                        } else {
+                            let _ = ufmt::uwrite!(string, "{}{}", value, separator);
+                        }
+                    }
+                    let _ =
+                        Text::new(&string, SECONDARY_POSITION, battery_style).draw(&mut display);
+                }
+
+                if frame.time.is_none() {
+                    let _ =
+                        Text::new("syncing...", SYNCING_POSITION, battery_style).draw(&mut display);
+                }
+
+                if let Some(date) = frame.date.filter(|_| grayscale.is_none()) {
+                    let _ = draw_date(&mut display, date, face.text_font, Color::Black);
+                }
+
+                if let Some(string) = &frame.stopwatch {
+                    let _ = Text::new(string, STOPWATCH_POSITION, battery_style).draw(&mut display);
+                }
+
+                if let Some(temperature) = frame.temperature {
+                    let mut string = heapless::String::<8>::new();
+                    ufmt::uwrite!(string, "{}C", temperature).unwrap();
+                    let _ =
+                        Text::new(&string, Point::new(165, 195), battery_style).draw(&mut display);
+                }
+
+                if let Some(steps) = frame.steps {
+                    let mut string = heapless::String::<16>::new();
+                    ufmt::uwrite!(string, "{} steps", steps).unwrap();
+                    let _ = Text::new(&string, STEPS_POSITION, battery_style).draw(&mut display);
+                }
+
+                if let Some(bars) = frame.wifi_bars {
+                    let _ = draw_signal_bars(&mut display, bars);
+                }
+
+                if frame.alarm {
+                    let _ =
+                        Text::new("ALARM", Point::new(80, 150), battery_style).draw(&mut display);
+                }
+
+                if let Some(battery) = frame.battery {
                     let mut string = heapless::String::<20>::new();
 
+                    // the voltage jitters by a few mV every sample, so it isn't
+                    // part of the frame and only updates when something else does
+                    let voltage = crate::BATTERY_STATUS
+                        .peek()
+                        .map(|status| status.voltage())
+                        .unwrap_or_default();
+
                     ufmt::uwrite!(
                         string,
                         "{}mV ({}%) {}",
-                        bat.voltage(),
-                        bat.percentage(),
-                        match battery.charging().await {
-                            true => "+",
-                            false => "",
+                        voltage,
+                        battery,
+                        match frame.charge_state {
+                            crate::ChargeState::Discharging => "",
+                            crate::ChargeState::Charging => "+",
+                            crate::ChargeState::Full => "full",
                         }
                     )
                     .unwrap();
@@ -166,18 +860,69 @@ pub async fn drive_display(
                 display
             };
 
-            epd.update_frame(&mut spi, display.buffer(), &mut delay)
-                .unwrap();
+            // the gray parts go on top of the black and white frame
+            let gray_display = grayscale.is_some().then(|| {
+                let mut gray_display =
+                    Gray4Buffer::from_mono(display.buffer(), display_rotation(frame.orientation));
+                if let Some(battery) = frame.battery {
+                    let _ = draw_battery_gauge(&mut gray_display, battery);
+                }
+                if let Some(date) = frame.date {
+                    let _ = draw_date(&mut gray_display, date, face.text_font, DATE_SHADE);
+                }
+                gray_display
+            });
+
+            let refresh_start = Instant::now();
+
+            if let (Some(panel), Some(gray_display)) = (&mut grayscale, &gray_display) {
+                panel.draw(&mut spi, &mut delay, gray_display).unwrap();
+                record_refresh(Waveform::Grayscale, refresh_start.elapsed());
+            } else {
+                if partial {
+                    let (y, height) = STOPWATCH_ROWS;
+                    let row_bytes = WIDTH / 8;
+                    let rows = (y * row_bytes) as usize..((y + height) * row_bytes) as usize;
+                    epd.update_partial_frame(
+                        &mut spi,
+                        &mut delay,
+                        &display.buffer()[rows],
+                        0,
+                        y,
+                        WIDTH,
+                        height,
+                    )
+                    .unwrap();
+                } else {
+                    epd.update_frame(&mut spi, display.buffer(), &mut delay)
+                        .unwrap();
+                }
+
+                // Display updated frame
+                // epd.update_frame(&mut spi, display.buffer(), &mut delay)
+                //     .unwrap();
+                epd.display_frame(&mut spi, &mut delay).unwrap();
+                record_refresh(Waveform::Mono(current_lut), refresh_start.elapsed());
+            }
+            last_frame = Some(frame);
 
-            // Display updated frame
-            // epd.update_frame(&mut spi, display.buffer(), &mut delay)
-            //     .unwrap();
-            epd.display_frame(&mut spi, &mut delay).unwrap();
+            if BUSY_TIMED_OUT.swap(false, Ordering::Relaxed) {
+                defmt::error!("display timed out, resetting it");
+                // waking up hard resets and reinitialises the panel
+                epd.wake_up(&mut spi, &mut delay).unwrap();
+                // the frame may not have made it, so draw it again next time
+                last_frame = None;
+            }
 
-            defmt::info!("sleeping display");
+            // keep the panel awake while the stopwatch is ticking
+            if !crate::stopwatch::is_running() {
+                defmt::info!("sleeping display");
 
-            // Set the EPD to sleep
-            epd.sleep(&mut spi, &mut delay).unwrap();
+                // Set the EPD to sleep
+                epd.sleep(&mut spi, &mut delay).unwrap();
+                awake = false;
+                DISPLAY_ASLEEP.signal(true);
+            }
         }
     }
 }
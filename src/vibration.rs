@@ -0,0 +1,130 @@
+//! Vibration patterns, played through [`VIBRATION_SIGNAL`].
+//!
+//! The button task owns the motor and plays whatever pattern was signalled
+//! last, so a new pattern always replaces the one in progress. The motor is
+//! driven with pwm, so patterns can be played softly, see [`Vibration`].
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Timer};
+
+use crate::{CHARGING, VIBRATION_SIGNAL};
+
+/// The motor intensity for a full strength buzz.
+pub const FULL_INTENSITY: u8 = u8::MAX;
+/// A softer intensity, for taps that shouldn't be noticed across the room.
+pub const GENTLE_INTENSITY: u8 = 96;
+
+/// What the motor plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VibrationPattern {
+    /// A sequence of alternating on and off times, in milliseconds,
+    /// starting with on.
+    Steps(&'static [u64]),
+    /// A single buzz, in milliseconds.
+    Buzz(u64),
+}
+
+impl VibrationPattern {
+    /// How long step `i` lasts in milliseconds, or `None` once finished.
+    pub fn step(&self, i: usize) -> Option<u64> {
+        match self {
+            VibrationPattern::Steps(steps) => steps.get(i).copied(),
+            VibrationPattern::Buzz(millis) => (i == 0).then_some(*millis),
+        }
+    }
+
+    /// How long the pattern takes to play.
+    pub fn duration(&self) -> Duration {
+        match self {
+            VibrationPattern::Steps(steps) => Duration::from_millis(steps.iter().sum()),
+            VibrationPattern::Buzz(millis) => Duration::from_millis(*millis),
+        }
+    }
+}
+
+/// A pattern, and how hard to drive the motor while it's on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vibration {
+    pub pattern: VibrationPattern,
+    /// The pwm duty while on, from 0 for off to 255 for full strength.
+    pub intensity: u8,
+}
+
+impl Vibration {
+    /// Play `pattern` at full strength.
+    pub const fn new(pattern: VibrationPattern) -> Self {
+        Self {
+            pattern,
+            intensity: FULL_INTENSITY,
+        }
+    }
+
+    pub const fn with_intensity(self, intensity: u8) -> Self {
+        Self { intensity, ..self }
+    }
+}
+
+impl From<VibrationPattern> for Vibration {
+    fn from(pattern: VibrationPattern) -> Self {
+        Self::new(pattern)
+    }
+}
+
+/// A brief tick, for feedback on button presses.
+pub const TICK: VibrationPattern = VibrationPattern::Steps(&[60]);
+/// Two short buzzes.
+pub const SHORT_SHORT: VibrationPattern = VibrationPattern::Steps(&[80, 120, 80]);
+/// One long buzz.
+pub const LONG: VibrationPattern = VibrationPattern::Steps(&[400]);
+/// Two short buzzes and a long one, for notifications.
+pub const NOTIFY: VibrationPattern = VibrationPattern::Steps(&[80, 120, 80, 120, 400]);
+/// Three firm buzzes, for the alarm.
+pub const ALARM: VibrationPattern = VibrationPattern::Steps(&[200, 200, 200, 200, 200, 200]);
+
+/// The patterns to play when the charger is plugged in and unplugged.
+static CHARGING_PATTERNS: Mutex<
+    CriticalSectionRawMutex,
+    Cell<(VibrationPattern, VibrationPattern)>,
+> = Mutex::new(Cell::new((SHORT_SHORT, LONG)));
+
+/// Play a pattern, or a [`Vibration`] for a softer one, completing once it
+/// should have finished.
+///
+/// This doesn't know if another pattern cut it short.
+pub async fn play(vibration: impl Into<Vibration>) {
+    let vibration = vibration.into();
+    VIBRATION_SIGNAL.signal(vibration);
+    Timer::after(vibration.pattern.duration()).await;
+}
+
+/// Buzz at full strength for `duration_ms`, without waiting for it.
+pub fn buzz(duration_ms: u64) {
+    VIBRATION_SIGNAL.signal(Vibration::new(VibrationPattern::Buzz(duration_ms)));
+}
+
+/// Set the patterns played by [`notify_charging`] when the charger is
+/// plugged in and unplugged.
+pub fn set_charging_patterns(plug_in: VibrationPattern, unplug: VibrationPattern) {
+    CHARGING_PATTERNS.lock(|cell| cell.set((plug_in, unplug)));
+}
+
+/// Buzz whenever the charger is plugged in or unplugged.
+#[embassy_executor::task]
+pub async fn notify_charging() {
+    // the first reading is the state at boot, rather than a change
+    let mut charging = CHARGING.wait_for("initial charging state", Some).await;
+
+    loop {
+        let now = CHARGING.wait("charging changed").await;
+        if now == charging {
+            continue;
+        }
+        charging = now;
+
+        defmt::info!("charger {}", if now { "plugged in" } else { "unplugged" });
+        let (plug_in, unplug) = CHARGING_PATTERNS.lock(|cell| cell.get());
+        play(if now { plug_in } else { unplug }).await;
+    }
+}
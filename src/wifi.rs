@@ -2,16 +2,23 @@
 //!
 //! This module adds wifi support. To use it, start the wifi task and the net_task.
 //! The net_task drives the wifi stack while wifi connects to an IP and does stuff.
+//!
+//! This module is only built with the `wifi` feature. The network to join is
+//! passed to the [`wifi`] task, so it can come from anywhere, for example
+//! the build environment or credentials saved in NVS flash.
 
+use core::cell::Cell;
 use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering};
 use embassy_executor::Spawner;
-use embassy_futures::select::{select, Either};
-use embassy_net::udp::PacketMetadata;
-use embassy_net::{Config, Stack, StackResources};
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_net::dns::DnsSocket;
+use embassy_net::tcp::client::{TcpClient, TcpClientState};
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
 use embassy_sync::channel::Channel;
 use embassy_sync::signal::Signal;
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use esp_backtrace as _;
 use esp_hal::peripherals::RADIO_CLK;
 use esp_hal::peripherals::{RNG, WIFI};
@@ -20,38 +27,95 @@ use esp_hal::timer::{ErasedTimer, PeriodicTimer};
 use esp_wifi::{
     initialize,
     wifi::{
-        ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent, WifiStaDevice,
-        WifiState,
+        AuthMethod, ClientConfiguration, Configuration, WifiController, WifiDevice, WifiError,
+        WifiEvent, WifiStaDevice, WifiState,
     },
     EspWifiInitFor,
 };
+use reqwless::client::HttpClient;
+#[cfg(feature = "tls")]
+use reqwless::client::{TlsConfig, TlsVerify};
+use reqwless::request::Method;
+use reqwless::response::Status;
 use sntpc::NtpResult;
 use static_cell::StaticCell;
 
 use crate::sticky_signal::StickySignal;
+use crate::storage::Credentials;
 
 pub enum MessageType {
     TimeUpdate(&'static Signal<CriticalSectionRawMutex, TimeResponse>),
-    WeatherUpdate(&'static Signal<CriticalSectionRawMutex, WeatherResponse>),
 }
 
 pub type TimeResponse = Option<NtpResult>;
-pub struct WeatherResponse {}
 
 /// A bus for coordinating commands that can be actioned by the network task
 static NETWORK_BUS: Channel<CriticalSectionRawMutex, MessageType, 10> = Channel::new();
 static ENABLE_NETWORK: StickySignal<CriticalSectionRawMutex, bool, 4> =
     StickySignal::new_with_name("enable_network");
 
-static SSID: &str = "NOW1QQ9L";
-const PASSWORD: &str = include_str!("../wifi-password.txt");
+/// How many times to try syncing the time before giving up.
+const NTP_ATTEMPTS: u32 = 4;
+/// How long to wait after the first failed sync, doubling each time.
+const NTP_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Keeps the network up even when there are no requests, see [`hold_network`].
+static HOLD_NETWORK: AtomicBool = AtomicBool::new(false);
+
+/// The signal strength of the connected network in dBm, or `None` while
+/// disconnected. Updated by the connection task, see [`rssi`].
+pub static WIFI_RSSI: StickySignal<CriticalSectionRawMutex, Option<i8>, 4> =
+    StickySignal::new_with_name("wifi_rssi");
+
+/// How often to re-read the signal strength while connected.
+const RSSI_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Whether the radio is off, see [`wait_network_idle`].
+static RADIO_OFF: StickySignal<CriticalSectionRawMutex, bool, 4> =
+    StickySignal::new_with_name("radio_off");
+
+/// How long to wait after the first failed connect, doubling each time.
+const CONNECT_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// The longest to wait between connects, so we notice the network coming
+/// back into range.
+const MAX_CONNECT_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// When the connection task last gave up connecting, see [`wifi_available`].
+static GAVE_UP: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+/// How long to consider wifi down after giving up connecting.
+const GAVE_UP_COOLDOWN: Duration = Duration::from_secs(30 * 60);
 
 // new requests should just reuse existing values
 static TIME_SIGNAL: Signal<CriticalSectionRawMutex, TimeResponse> = Signal::new();
-static WEATHER_SIGNAL: Signal<CriticalSectionRawMutex, WeatherResponse> = Signal::new();
+
+/// Keep the network up until released, rather than only while there
+/// are requests to make, for example to stay reachable in recovery mode.
+///
+/// Once released, the network goes down after the next request.
+pub fn hold_network(hold: bool) {
+    defmt::info!("holding network: {}", hold);
+    HOLD_NETWORK.store(hold, Ordering::Relaxed);
+    if hold {
+        ENABLE_NETWORK.signal(true);
+    }
+}
+
+/// Whether wifi is worth trying, which is false for a while after
+/// repeatedly failing to connect, so that background tasks don't keep
+/// powering the radio up for an access point that isn't there.
+pub fn wifi_available() -> bool {
+    GAVE_UP.lock(|cell| {
+        cell.get()
+            .map_or(true, |at| at.elapsed() >= GAVE_UP_COOLDOWN)
+    })
+}
 
 pub async fn get_time() -> TimeResponse {
     // todo: avoid making already fulfilled requests
+    // drop any answer to an earlier request that was given up on, so it
+    // isn't mistaken for this one
+    TIME_SIGNAL.reset();
     let (time, _) = embassy_futures::join::join(
         TIME_SIGNAL.wait(),
         NETWORK_BUS.send(MessageType::TimeUpdate(&TIME_SIGNAL)),
@@ -61,40 +125,310 @@ pub async fn get_time() -> TimeResponse {
     time
 }
 
-pub async fn get_weather() -> WeatherResponse {
-    // todo: avoid making already fulfilled requests
-    let (weather, _) = embassy_futures::join::join(
-        WEATHER_SIGNAL.wait(),
-        NETWORK_BUS.send(MessageType::WeatherUpdate(&WEATHER_SIGNAL)),
-    )
-    .await;
+/// The network stack used for wifi.
+pub type WifiStack = Stack<WifiDevice<'static, WifiStaDevice>>;
+
+#[derive(Debug, defmt::Format)]
+pub enum HttpError {
+    /// The request could not be made, or the response could not be read.
+    Request(reqwless::Error),
+    /// The server responded with a non-2xx status.
+    Status(Status),
+    /// The TLS handshake with the server failed.
+    #[cfg(feature = "tls")]
+    Tls(reqwless::Error),
+}
+
+impl From<reqwless::Error> for HttpError {
+    fn from(e: reqwless::Error) -> Self {
+        match e {
+            #[cfg(feature = "tls")]
+            e @ reqwless::Error::Tls(_) => HttpError::Tls(e),
+            e => HttpError::Request(e),
+        }
+    }
+}
+
+/// The size of the TLS record buffers. Reads have to fit the largest
+/// record a server may send.
+#[cfg(feature = "tls")]
+const TLS_READ_BUFFER_SIZE: usize = 16640;
+#[cfg(feature = "tls")]
+const TLS_WRITE_BUFFER_SIZE: usize = 4096;
+
+/// The TLS buffers, which are too big for a task's stack, so https
+/// requests take turns.
+#[cfg(feature = "tls")]
+static TLS_BUFFERS: embassy_sync::mutex::Mutex<
+    CriticalSectionRawMutex,
+    ([u8; TLS_READ_BUFFER_SIZE], [u8; TLS_WRITE_BUFFER_SIZE]),
+> = embassy_sync::mutex::Mutex::new(([0; TLS_READ_BUFFER_SIZE], [0; TLS_WRITE_BUFFER_SIZE]));
+
+/// Seeds the TLS random number generator, set from the hardware rng when
+/// the wifi task starts.
+#[cfg(feature = "tls")]
+static TLS_SEED: Mutex<CriticalSectionRawMutex, Cell<u64>> = Mutex::new(Cell::new(0));
+
+/// Fetch `url`, returning the body.
+///
+/// The response is read into an `N` byte buffer on the stack, which has to
+/// fit the headers as well as the body, otherwise this fails with
+/// [`reqwless::Error::BufferTooSmall`]. Responses with a non-2xx status are
+/// returned as errors.
+///
+/// With the `tls` feature, `https://` urls are fetched over TLS. The
+/// server certificate isn't verified, so this protects against snooping
+/// but not impersonation.
+///
+/// ```no_run
+/// # async fn example(stack: &'static watchy_rs::WifiStack) {
+/// let body = watchy_rs::http_get::<2048>(stack, "https://example.com").await;
+/// # }
+/// ```
+pub async fn http_get<const N: usize>(
+    stack: &'static WifiStack,
+    url: &str,
+) -> Result<heapless::Vec<u8, N>, HttpError> {
+    let mut buf = [0; N];
+    let state = TcpClientState::<1, 1024, 1024>::new();
+    let tcp = TcpClient::new(stack, &state);
+    let dns = DnsSocket::new(stack);
+
+    #[cfg(feature = "tls")]
+    let mut tls_buffers = TLS_BUFFERS.lock().await;
+    #[cfg(feature = "tls")]
+    let mut client = if url.starts_with("https://") {
+        let (read, write) = &mut *tls_buffers;
+        let seed = TLS_SEED.lock(|cell| cell.get());
+        let config = TlsConfig::new(seed, read, write, TlsVerify::None);
+        HttpClient::new_with_tls(&tcp, &dns, config)
+    } else {
+        HttpClient::new(&tcp, &dns)
+    };
+    #[cfg(not(feature = "tls"))]
+    let mut client = HttpClient::new(&tcp, &dns);
+
+    defmt::info!("GET {}", url);
+    let mut request = client.request(Method::GET, url).await?;
+    let response = request.send(&mut buf).await?;
 
-    weather
+    if !response.status.is_successful() {
+        defmt::warn!("GET {} failed with {}", url, response.status);
+        return Err(HttpError::Status(response.status));
+    }
+
+    let body = response.body().read_to_end().await?;
+    // the body is read into `buf`, so it always fits
+    Ok(heapless::Vec::from_slice(body).unwrap_or_default())
+}
+
+/// How the network stack gets its address.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WifiConfig {
+    /// Ask the network for an address.
+    #[default]
+    Dhcp,
+    /// Use a fixed address, for networks without dhcp or to connect faster.
+    Static {
+        address: Ipv4Address,
+        /// The length of the subnet prefix, for example 24 for a /24.
+        prefix_len: u8,
+        gateway: Option<Ipv4Address>,
+        dns: Option<Ipv4Address>,
+    },
+}
+
+/// Why a [`WifiConfig::Static`] can't be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum StaticIpError {
+    /// The prefix is longer than 32 bits.
+    InvalidPrefix,
+    /// The address is unspecified, the network address or the broadcast address.
+    InvalidAddress,
+    /// The gateway isn't in the same subnet as the address.
+    GatewayOutsideSubnet,
+}
+
+impl WifiConfig {
+    /// The config for the network stack, falling back to dhcp with a
+    /// warning if the static config is malformed.
+    pub fn net_config(&self) -> Config {
+        match self.static_config() {
+            Ok(Some(config)) => Config::ipv4_static(config),
+            Ok(None) => Config::dhcpv4(Default::default()),
+            Err(e) => {
+                defmt::warn!("invalid static ip config ({}), using dhcp", e);
+                Config::dhcpv4(Default::default())
+            }
+        }
+    }
+
+    /// Validate a static config, or `None` for dhcp.
+    pub fn static_config(&self) -> Result<Option<StaticConfigV4>, StaticIpError> {
+        let WifiConfig::Static {
+            address,
+            prefix_len,
+            gateway,
+            dns,
+        } = *self
+        else {
+            return Ok(None);
+        };
+
+        if prefix_len > 32 {
+            return Err(StaticIpError::InvalidPrefix);
+        }
+
+        let cidr = Ipv4Cidr::new(address, prefix_len);
+        let is_edge = prefix_len < 31
+            && (address == cidr.network().address() || Some(address) == cidr.broadcast());
+        if address.is_unspecified() || is_edge {
+            return Err(StaticIpError::InvalidAddress);
+        }
+
+        if gateway.is_some_and(|gateway| !cidr.contains_addr(&gateway)) {
+            return Err(StaticIpError::GatewayOutsideSubnet);
+        }
+
+        Ok(Some(StaticConfigV4 {
+            address: cidr,
+            gateway,
+            dns_servers: dns.into_iter().collect(),
+        }))
+    }
+}
+
+/// A visible wifi network, as found by [`scan`].
+#[derive(Debug, Clone)]
+pub struct AccessPoint {
+    pub ssid: heapless::String<32>,
+    /// The signal strength in dBm.
+    pub rssi: i8,
+    /// How the network is secured, or `None` if unknown.
+    pub auth_method: Option<AuthMethod>,
+}
+
+impl defmt::Format for AccessPoint {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "{} ({}dBm, {})",
+            self.ssid.as_str(),
+            self.rssi,
+            defmt::Debug2Format(&self.auth_method)
+        )
+    }
+}
+
+/// The signal strength of the connected network in dBm, or `None` if not
+/// connected.
+///
+/// This asks the driver for the last beacon's strength rather than
+/// scanning, so it is cheap enough to call on every draw.
+pub fn rssi(controller: &WifiController<'_>) -> Option<i8> {
+    if !matches!(controller.is_connected(), Ok(true)) {
+        return None;
+    }
+
+    // safety: the record is plain data, which the driver fills in
+    let mut record: esp_wifi_sys::include::wifi_ap_record_t = unsafe { core::mem::zeroed() };
+    let result = unsafe { esp_wifi_sys::include::esp_wifi_sta_get_ap_info(&mut record) };
+    (result == esp_wifi_sys::include::ESP_OK as i32).then_some(record.rssi)
+}
+
+/// Map a signal strength in dBm to 0 to 4 bars, for a signal icon.
+pub fn signal_bars(rssi: i8) -> u8 {
+    match rssi {
+        -55.. => 4,
+        -67..=-56 => 3,
+        -75..=-68 => 2,
+        -85..=-76 => 1,
+        _ => 0,
+    }
+}
+
+/// Disconnect and stop the radio, returning it to a low power state.
+///
+/// A connected station draws around 100mA with the radio receiving, and
+/// bursts of up to 300mA while transmitting. With the radio stopped the
+/// chip draws a few tens of mA at 80MHz, and deep sleep only works once
+/// it's off, after which the watch draws around 10µA.
+pub async fn shutdown(controller: &mut WifiController<'_>) -> Result<(), WifiError> {
+    if matches!(controller.is_connected(), Ok(true)) {
+        controller.disconnect().await?;
+    }
+    controller.stop().await?;
+
+    WIFI_RSSI.signal_if_changed(None);
+    RADIO_OFF.signal(true);
+    defmt::info!("wifi shut down");
+    Ok(())
+}
+
+/// Wait until there is no network work left and the radio is off, for
+/// example before deep sleeping.
+///
+/// Returns straight away if the radio was never turned on.
+pub async fn wait_network_idle() {
+    RADIO_OFF
+        .wait_for("wait network idle", |off| off.then_some(()))
+        .await
+}
+
+/// Scan for up to `N` visible networks, starting the controller if needed.
+pub async fn scan<const N: usize>(
+    controller: &mut WifiController<'_>,
+) -> Result<heapless::Vec<AccessPoint, N>, WifiError> {
+    if !matches!(controller.is_started(), Ok(true)) {
+        controller.start().await?;
+    }
+
+    let (results, found) = controller.scan_n::<N>().await?;
+    defmt::info!("found {} networks", found);
+
+    Ok(results
+        .into_iter()
+        .map(|info| AccessPoint {
+            ssid: info.ssid,
+            rssi: info.signal_strength,
+            auth_method: info.auth_method,
+        })
+        .collect())
 }
 
 static STACK_RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
-static WIFI_STACK: StaticCell<Stack<WifiDevice<'static, WifiStaDevice>>> = StaticCell::new();
+static WIFI_STACK: StaticCell<WifiStack> = StaticCell::new();
 
+/// Bring up the network stack and serve requests, joining the network
+/// whenever there is something to do.
+///
+/// Credentials saved with [`crate::save_wifi_credentials`] are preferred,
+/// falling back to `ssid` and `password`. The address is set by `config`.
 #[embassy_executor::task]
 pub async fn wifi(
+    ssid: &'static str,
+    password: &'static str,
+    config: WifiConfig,
     timer: PeriodicTimer<'static, ErasedTimer>,
     rng: RNG,
     radio_clock_control: RADIO_CLK,
     wifi: WIFI,
     spawner: Spawner,
 ) {
-    let init = initialize(
-        EspWifiInitFor::Wifi,
-        timer,
-        Rng::new(rng),
-        radio_clock_control,
-    )
-    .unwrap();
+    let rng = Rng::new(rng);
+    #[cfg(feature = "tls")]
+    {
+        let mut rng = rng;
+        let seed = u64::from(rng.random()) << 32 | u64::from(rng.random());
+        TLS_SEED.lock(|cell| cell.set(seed));
+    }
+
+    let init = initialize(EspWifiInitFor::Wifi, timer, rng, radio_clock_control).unwrap();
 
     let (wifi_interface, controller) =
         esp_wifi::wifi::new_with_mode(&init, wifi, WifiStaDevice).unwrap();
 
-    let config = Config::dhcpv4(Default::default());
+    let config = config.net_config();
 
     let seed = 1234; // very random, very secure seed
 
@@ -107,81 +441,107 @@ pub async fn wifi(
     );
     let stack = WIFI_STACK.init(stack);
 
-    spawner.spawn(connection(controller)).ok();
+    spawner.spawn(connection(controller, ssid, password)).ok();
     spawner.spawn(net_task(stack)).ok();
 
     loop {
         let msg = NETWORK_BUS.receive().await;
         ENABLE_NETWORK.signal(true);
 
-        loop {
-            if stack.is_link_up() {
-                break;
-            }
-            Timer::after(Duration::from_millis(100)).await;
-        }
-
-        defmt::info!("Waiting to get IP address...");
-        loop {
-            if let Some(config) = stack.config_v4() {
-                defmt::info!("Got IP: {}", config.address);
-                break;
+        // the connection task disables the network when it gives up, and
+        // won't try again until asked, so don't wait on it forever
+        if !wait_for_address(stack).await {
+            defmt::warn!("network disabled before connecting, failing request");
+            match msg {
+                MessageType::TimeUpdate(sig) => sig.signal(None),
             }
-            Timer::after(Duration::from_millis(100)).await;
+            continue;
         }
 
         match msg {
             MessageType::TimeUpdate(sig) => {
-                let mut rx_meta = [PacketMetadata::EMPTY; 16];
-                let mut rx_buffer = [0; 4096];
-                let mut tx_meta = [PacketMetadata::EMPTY; 16];
-                let mut tx_buffer = [0; 4096];
-                let mut socket = embassy_net::udp::UdpSocket::new(
-                    stack,
-                    &mut rx_meta,
-                    &mut rx_buffer,
-                    &mut tx_meta,
-                    &mut tx_buffer,
-                );
-                socket.bind(9400).unwrap();
                 defmt::info!("getting time");
-                let res = crate::time::get_time(socket).await;
+                let res =
+                    crate::time::get_time_with_retry(stack, NTP_ATTEMPTS, NTP_RETRY_DELAY).await;
                 defmt::info!("sending result {}", res.is_some());
                 sig.signal(res);
             }
-            _ => unimplemented!(),
         }
 
-        if NETWORK_BUS.is_empty() {
+        if NETWORK_BUS.is_empty() && !HOLD_NETWORK.load(Ordering::Relaxed) {
             ENABLE_NETWORK.signal(false);
         }
     }
 }
 
+/// Wait for the link to come up and get an address, returning `false` if
+/// the network is disabled first.
+async fn wait_for_address(stack: &WifiStack) -> bool {
+    let connected = async {
+        while !stack.is_link_up() {
+            Timer::after(Duration::from_millis(100)).await;
+        }
+
+        defmt::info!("Waiting to get IP address...");
+        loop {
+            if let Some(config) = stack.config_v4() {
+                defmt::info!("Got IP: {}", config.address);
+                break;
+            }
+            Timer::after(Duration::from_millis(100)).await;
+        }
+    };
+
+    match select(
+        ENABLE_NETWORK.wait_for("wait for address", |val| (!val).then_some(())),
+        connected,
+    )
+    .await
+    {
+        Either::First(()) => false,
+        Either::Second(()) => true,
+    }
+}
+
 /// Connect and disconnect to wifi depending on if there are requests queued.
 #[embassy_executor::task]
-async fn connection(mut controller: WifiController<'static>) {
+async fn connection(
+    mut controller: WifiController<'static>,
+    ssid: &'static str,
+    password: &'static str,
+) {
     defmt::info!("start connection task");
+    RADIO_OFF.signal(true);
     let mut connect_failures = 0;
+    // unlike `connect_failures` this isn't reset when giving up, so
+    // the backoff continues if we're asked to connect again
+    let mut consecutive_failures = 0;
     const MAX_CONNECT_FAILURES: usize = 3;
     loop {
         defmt::trace!("wifi loop");
         if esp_wifi::wifi::get_wifi_state() == WifiState::StaConnected {
-            match select(
+            WIFI_RSSI.signal_if_changed(rssi(&controller));
+            match select3(
                 ENABLE_NETWORK.wait_for("wifi loop disabled", |val| (!val).then_some(false)),
                 controller.wait_for_event(WifiEvent::StaDisconnected),
+                Timer::after(RSSI_INTERVAL),
             )
             .await
             {
                 // disconnect
-                Either::First(_) => {
+                Either3::First(_) => {
                     defmt::info!("stopping wifi");
-                    controller.stop().await.unwrap();
+                    if let Err(e) = shutdown(&mut controller).await {
+                        defmt::error!("failed to shut down wifi {:?}", e);
+                    }
                 }
                 // we disconnected involuntarily, attempt to reconnect
-                Either::Second(_) => {
+                Either3::Second(_) => {
+                    WIFI_RSSI.signal(None);
                     Timer::after(Duration::from_millis(5000)).await;
                 }
+                // still connected, loop round to update the signal strength
+                Either3::Third(_) => continue,
             };
         }
         if !matches!(controller.is_started(), Ok(true)) {
@@ -190,38 +550,66 @@ async fn connection(mut controller: WifiController<'static>) {
                 .wait_for("wait to start wifi", |val| val.then_some(true))
                 .await;
 
+            let credentials = match crate::storage::load_wifi_credentials() {
+                Some(credentials) => credentials,
+                None => Credentials {
+                    ssid: heapless::String::from_str(ssid).unwrap(),
+                    password: heapless::String::from_str(password).unwrap(),
+                },
+            };
             let client_config = Configuration::Client(ClientConfiguration {
-                ssid: heapless::String::from_str(SSID).unwrap(),
-                password: heapless::String::from_str(PASSWORD).unwrap(),
+                ssid: credentials.ssid,
+                password: credentials.password,
 
                 ..Default::default()
             });
             controller.set_configuration(&client_config).unwrap();
             defmt::info!("Starting wifi");
+            RADIO_OFF.signal(false);
             let data = controller.start().await;
             defmt::info!("Wifi started! {:?}", data);
         }
         defmt::info!("About to connect...");
 
         match controller.connect().await {
-            Ok(()) => defmt::info!("Wifi connected!"),
+            Ok(()) => {
+                defmt::info!("Wifi connected!");
+                GAVE_UP.lock(|cell| cell.set(None));
+                connect_failures = 0;
+                consecutive_failures = 0;
+            }
             Err(e) => {
                 defmt::info!("Failed to connect to wifi {:?}", e);
                 connect_failures += 1;
+                consecutive_failures += 1;
                 if connect_failures > MAX_CONNECT_FAILURES {
                     defmt::info!("Shutting down wifi");
+                    GAVE_UP.lock(|cell| cell.set(Some(Instant::now())));
                     ENABLE_NETWORK.signal(false);
-                    controller.stop().await.unwrap();
+                    if let Err(e) = shutdown(&mut controller).await {
+                        defmt::error!("failed to shut down wifi {:?}", e);
+                    }
                     connect_failures = 0;
                 }
-                Timer::after(Duration::from_millis(5000)).await
+
+                let delay = connect_retry_delay(consecutive_failures);
+                defmt::info!("retrying connect in {}s", delay.as_secs());
+                Timer::after(delay).await
             }
         }
     }
 }
 
+/// How long to wait before reconnecting after `failures` failed connects
+/// in a row, doubling from [`CONNECT_RETRY_DELAY`] up to
+/// [`MAX_CONNECT_RETRY_DELAY`].
+fn connect_retry_delay(failures: u32) -> Duration {
+    let factor = 1 << failures.saturating_sub(1).min(16);
+    (CONNECT_RETRY_DELAY * factor).min(MAX_CONNECT_RETRY_DELAY)
+}
+
 #[embassy_executor::task]
-async fn net_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
+async fn net_task(stack: &'static WifiStack) {
     // wait for network to be enabled, then select on it being disabled
 
     loop {
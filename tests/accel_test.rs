@@ -0,0 +1,94 @@
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use time::{Date, Month};
+    use watchy_rs::accel::{DailySteps, Orientation, OrientationTracker, TiltSensitivity};
+
+    fn day(day: u8) -> Date {
+        Date::from_calendar_date(2024, Month::July, day).unwrap()
+    }
+
+    #[test]
+    fn test_first_reading_is_baseline() {
+        let mut steps = DailySteps::new();
+        assert_eq!(steps.update(1200, day(1)), 0);
+        assert_eq!(steps.update(1250, day(1)), 50);
+    }
+
+    #[test]
+    fn test_day_rollover() {
+        let mut steps = DailySteps::new();
+        steps.update(1000, day(1));
+        assert_eq!(steps.update(3000, day(1)), 2000);
+        assert_eq!(steps.update(3100, day(2)), 0);
+        assert_eq!(steps.update(3400, day(2)), 300);
+    }
+
+    #[test]
+    fn test_counter_reset_carries_over() {
+        let mut steps = DailySteps::new();
+        steps.update(1000, day(1));
+        assert_eq!(steps.update(1500, day(1)), 500);
+        // the accelerometer was reset
+        assert_eq!(steps.update(20, day(1)), 520);
+        assert_eq!(steps.update(100, day(1)), 600);
+    }
+
+    #[test]
+    fn test_tilt_sensitivity() {
+        // face up is -z, at 1024 per g
+        let flat = (0, 0, -1024);
+        let angled = (0, 700, -600);
+        let vertical = (1024, 0, 0);
+
+        assert!(TiltSensitivity::Low.is_raised(flat));
+        assert!(!TiltSensitivity::Low.is_raised(angled));
+        assert!(!TiltSensitivity::Medium.is_raised(angled));
+        assert!(TiltSensitivity::High.is_raised(angled));
+        assert!(!TiltSensitivity::High.is_raised(vertical));
+    }
+
+    #[test]
+    fn test_orientation_from_vector() {
+        // 1024 per g, +x is vertical and +y is rotated left
+        assert_eq!(
+            Orientation::from_vector((1000, 100, -200)),
+            Some(Orientation::Normal)
+        );
+        assert_eq!(
+            Orientation::from_vector((-900, 200, 300)),
+            Some(Orientation::Upside)
+        );
+        assert_eq!(
+            Orientation::from_vector((100, 950, -100)),
+            Some(Orientation::RotatedLeft)
+        );
+        assert_eq!(
+            Orientation::from_vector((-150, -980, 0)),
+            Some(Orientation::RotatedRight)
+        );
+    }
+
+    #[test]
+    fn test_orientation_flat() {
+        assert_eq!(Orientation::from_vector((100, -50, -1020)), None);
+
+        let mut tracker = OrientationTracker::new();
+        tracker.update((0, 1000, 0));
+        assert_eq!(tracker.update((100, -50, -1020)), Orientation::RotatedLeft);
+    }
+
+    #[test]
+    fn test_orientation_hysteresis() {
+        let mut tracker = OrientationTracker::new();
+        assert_eq!(tracker.update((800, 0, -600)), Orientation::Normal);
+
+        // just past 45 degrees isn't enough to switch
+        assert_eq!(tracker.update((700, 750, 0)), Orientation::Normal);
+        assert_eq!(tracker.update((600, 800, 0)), Orientation::Normal);
+
+        // but clearly turned is
+        assert_eq!(tracker.update((400, 900, 0)), Orientation::RotatedLeft);
+        assert_eq!(tracker.update((700, 750, 0)), Orientation::RotatedLeft);
+    }
+}
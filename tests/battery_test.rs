@@ -0,0 +1,176 @@
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use watchy_rs::*;
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(voltage_to_percentage(3400), 0);
+    }
+
+    #[test]
+    fn test_full() {
+        assert_eq!(voltage_to_percentage(4200), 100);
+    }
+
+    #[test]
+    fn test_midpoints() {
+        assert_eq!(voltage_to_percentage(3600), 5);
+        assert_eq!(voltage_to_percentage(3850), 50);
+        assert_eq!(voltage_to_percentage(4000), 82);
+    }
+
+    #[test]
+    fn test_interpolates_between_points() {
+        assert_eq!(voltage_to_percentage(3500), 2);
+        assert_eq!(voltage_to_percentage(3865), 55);
+    }
+
+    #[test]
+    fn test_custom_curve() {
+        let curve = [(3000, 0), (4000, 100)];
+        assert_eq!(curve_percentage(&curve, 3500), 50);
+        assert_eq!(curve_voltage(&curve, 50), 3500);
+        assert_eq!(curve_percentage(&[], 3500), 0);
+    }
+
+    #[test]
+    fn test_saturates() {
+        assert_eq!(voltage_to_percentage(0), 0);
+        assert_eq!(voltage_to_percentage(3399), 0);
+        assert_eq!(voltage_to_percentage(5000), 100);
+    }
+
+    #[test]
+    fn test_status_percentage() {
+        assert_eq!(BatteryStatus::from_voltage(3850).percentage(), 50);
+    }
+
+    #[test]
+    fn test_charging_thresholds() {
+        let mut detector = ChargeDetector::new();
+        assert!(!detector.update(CHARGING_ON_MV));
+        assert!(detector.update(CHARGING_ON_MV + 1));
+        assert!(detector.update(CHARGING_OFF_MV));
+        assert!(!detector.update(CHARGING_OFF_MV - 1));
+    }
+
+    #[test]
+    fn test_scale_voltage() {
+        assert_eq!(scale_voltage(0), 0);
+        assert!((4599..=4600).contains(&scale_voltage(3600)));
+    }
+
+    #[test]
+    fn test_inverse_anchors() {
+        assert_eq!(BatteryStatus::voltage_for_percentage(0), 3400);
+        assert_eq!(BatteryStatus::voltage_for_percentage(50), 3850);
+        assert_eq!(BatteryStatus::voltage_for_percentage(100), 4200);
+    }
+
+    #[test]
+    fn test_inverse_round_trip() {
+        let mut last = 0;
+        for percentage in 0..=100 {
+            let voltage = BatteryStatus::voltage_for_percentage(percentage);
+            assert!(voltage >= last);
+            assert_eq!(voltage_to_percentage(voltage), percentage);
+            last = voltage;
+        }
+    }
+
+    #[test]
+    fn test_default_attenuation_clips_full() {
+        // a full battery puts 3287mV on the pin, past the 3100mV range
+        let error = check_attenuation(DEFAULT_ATTENUATION).unwrap_err();
+        assert_eq!(error.required_mv, 3287);
+        assert_eq!(error.max_mv, 3100);
+        assert_eq!(
+            max_battery_mv(DEFAULT_ATTENUATION, VoltageDivider::WATCHY),
+            3961
+        );
+    }
+
+    #[test]
+    fn test_default_attenuation_usable() {
+        assert!(check_attenuation_usable(DEFAULT_ATTENUATION, VoltageDivider::WATCHY).is_ok());
+        assert!(check_attenuation_usable(
+            esp_hal::analog::adc::Attenuation::Attenuation6dB,
+            VoltageDivider::WATCHY
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_low_attenuation_clips() {
+        let error =
+            check_attenuation(esp_hal::analog::adc::Attenuation::Attenuation6dB).unwrap_err();
+        assert!(error.required_mv > error.max_mv);
+    }
+
+    #[test]
+    fn test_divider_round_trip() {
+        let divider = VoltageDivider::new(100, 100);
+        assert_eq!(divider.scale(2000), 4000);
+        assert_eq!(divider.divide(4000), 2000);
+        assert_eq!(VoltageDivider::WATCHY.scale(3600), scale_voltage(3600));
+    }
+
+    #[test]
+    fn test_charge_state() {
+        assert_eq!(
+            ChargeState::from_readings(false, 4200),
+            ChargeState::Discharging
+        );
+        assert_eq!(
+            ChargeState::from_readings(true, FULL_CHARGE_MV - 1),
+            ChargeState::Charging
+        );
+        assert_eq!(
+            ChargeState::from_readings(true, FULL_CHARGE_MV),
+            ChargeState::Full
+        );
+        assert!(ChargeState::Full.is_plugged_in());
+    }
+
+    #[test]
+    fn test_charge_hysteresis() {
+        let mut detector = ChargeDetector::new();
+
+        // hovering around the threshold never turns it on
+        for reading in [2950, 3050, 2990, 3100, 3010] {
+            assert!(!detector.update(reading));
+        }
+
+        assert!(detector.update(CHARGING_ON_MV + 1));
+
+        // and once on, hovering never turns it off
+        for reading in [3050, 2950, 2990, 3100, 2900] {
+            assert!(detector.update(reading));
+        }
+
+        assert!(!detector.update(CHARGING_OFF_MV - 1));
+    }
+
+    #[test]
+    fn test_time_to_empty() {
+        use embassy_time::{Duration, Instant};
+
+        let mut estimator = DischargeEstimator::<8>::new();
+        estimator.record(Instant::from_secs(0), 3800, false);
+        estimator.record(Instant::from_secs(3600), 3790, false);
+        assert_eq!(estimator.time_to_empty(), None);
+
+        estimator.record(Instant::from_secs(7200), 3780, false);
+        let remaining = estimator.time_to_empty().unwrap();
+        assert!(
+            remaining
+                .as_secs()
+                .abs_diff(Duration::from_secs(38 * 3600).as_secs())
+                < 60
+        );
+
+        estimator.record(Instant::from_secs(7300), 3780, true);
+        assert_eq!(estimator.time_to_empty(), None);
+    }
+}
@@ -0,0 +1,90 @@
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use core::convert::Infallible;
+
+    use embedded_hal::delay::DelayNs;
+    use embedded_hal::digital::{ErrorType as PinErrorType, InputPin, OutputPin};
+    use embedded_hal::spi::{ErrorType as SpiErrorType, Operation, SpiDevice};
+    use watchy_rs::{Watchy1in54, Watchy1in54V2, Watchy2in9V2};
+
+    /// Accepts every transfer, counting the writes.
+    #[derive(Default)]
+    struct MockSpi {
+        writes: usize,
+    }
+
+    impl SpiErrorType for MockSpi {
+        type Error = Infallible;
+    }
+
+    impl SpiDevice for MockSpi {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+            self.writes += operations
+                .iter()
+                .filter(|op| matches!(op, Operation::Write(_)))
+                .count();
+            Ok(())
+        }
+    }
+
+    /// A pin that is always low, so the panel never reads as busy.
+    struct MockPin;
+
+    impl PinErrorType for MockPin {
+        type Error = Infallible;
+    }
+
+    impl InputPin for MockPin {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(false)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(true)
+        }
+    }
+
+    impl OutputPin for MockPin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn test_new_and_draw() {
+        let mut display =
+            Watchy1in54V2::new(MockSpi::default(), MockPin, MockPin, MockPin, NoDelay).unwrap();
+
+        let (_, spi, _) = display.parts();
+        let init_writes = spi.writes;
+        assert!(init_writes > 0);
+
+        display.draw_test().unwrap();
+        let (_, spi, _) = display.parts();
+        assert!(spi.writes > init_writes);
+    }
+
+    #[test]
+    fn test_other_panels() {
+        let mut v1 =
+            Watchy1in54::new(MockSpi::default(), MockPin, MockPin, MockPin, NoDelay).unwrap();
+        v1.draw_test().unwrap();
+        v1.show_message("hello").unwrap();
+
+        let mut large =
+            Watchy2in9V2::new(MockSpi::default(), MockPin, MockPin, MockPin, NoDelay).unwrap();
+        large.draw_test().unwrap();
+        large.show_message("hello").unwrap();
+    }
+}
@@ -2,6 +2,8 @@
 #[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
 mod tests {
     use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_time::Duration;
+    use watchy_rs::clock::MockClock;
     use watchy_rs::sticky_signal::*;
 
     #[derive(Copy, Clone, PartialEq, Debug)]
@@ -12,40 +14,204 @@ mod tests {
 
     #[test]
     fn test_signal() {
-        static SIGNAL: StickySignal<NoopRawMutex, TestCommand> = StickySignal::new();
-        SIGNAL.signal(TestCommand::Start);
-        assert_eq!(SIGNAL.peek(), Some(TestCommand::Start));
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 1>::new();
+        signal.signal(TestCommand::Start);
+        assert_eq!(signal.peek(), Some(TestCommand::Start));
     }
 
     #[test]
     fn test_reset() {
-        static SIGNAL: StickySignal<NoopRawMutex, TestCommand> = StickySignal::new();
-        SIGNAL.signal(TestCommand::Start);
-        SIGNAL.reset();
-        assert_eq!(SIGNAL.peek(), None);
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 1>::new();
+        signal.signal(TestCommand::Start);
+        signal.reset();
+        assert_eq!(signal.peek(), None);
     }
 
     #[test]
     fn test_try_take() {
-        static SIGNAL: StickySignal<NoopRawMutex, TestCommand> = StickySignal::new();
-        SIGNAL.signal(TestCommand::Start);
-        assert_eq!(SIGNAL.try_take(), Some(TestCommand::Start));
-        assert_eq!(SIGNAL.try_take(), None);
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 1>::new();
+        signal.signal(TestCommand::Start);
+        assert_eq!(signal.try_take(), Some(TestCommand::Start));
+        assert_eq!(signal.try_take(), None);
     }
 
     #[test]
     fn test_is_signaled() {
-        static SIGNAL: StickySignal<NoopRawMutex, TestCommand> = StickySignal::new();
-        assert!(!SIGNAL.is_signaled());
-        SIGNAL.signal(TestCommand::Start);
-        assert!(SIGNAL.is_signaled());
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 1>::new();
+        assert!(!signal.is_signaled());
+        signal.signal(TestCommand::Start);
+        assert!(signal.is_signaled());
+    }
+
+    #[test]
+    fn test_signal_if_changed() {
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 2>::new();
+        assert!(signal.signal_if_changed(TestCommand::Start));
+        assert!(!signal.signal_if_changed(TestCommand::Start));
+        assert!(signal.signal_if_changed(TestCommand::Stop));
+        assert_eq!(signal.peek(), Some(TestCommand::Stop));
     }
 
     #[test]
     fn test_peek() {
-        static SIGNAL: StickySignal<NoopRawMutex, TestCommand> = StickySignal::new();
-        assert_eq!(SIGNAL.peek(), None);
-        SIGNAL.signal(TestCommand::Start);
-        assert_eq!(SIGNAL.peek(), Some(TestCommand::Start));
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 1>::new();
+        assert_eq!(signal.peek(), None);
+        signal.signal(TestCommand::Start);
+        assert_eq!(signal.peek(), Some(TestCommand::Start));
+    }
+
+    #[test]
+    async fn test_wait_timeout_with_mock_clock() {
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 2>::new();
+        let clock = MockClock::new();
+
+        let (res, _) = embassy_futures::join::join(
+            signal.wait_timeout_with("test", Duration::from_secs(1), &clock),
+            async { clock.advance(Duration::from_secs(1)) },
+        )
+        .await;
+        assert_eq!(res, None);
+    }
+
+    #[test]
+    async fn test_wait_timeout_with_signal() {
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 2>::new();
+        let clock = MockClock::new();
+
+        let (res, _) = embassy_futures::join::join(
+            signal.wait_timeout_with("test", Duration::from_secs(1), &clock),
+            async { signal.signal(TestCommand::Stop) },
+        )
+        .await;
+        assert_eq!(res, Some(TestCommand::Stop));
+    }
+
+    #[test]
+    fn test_drop_waiter() {
+        use embassy_futures::poll_once;
+
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 4>::new();
+        let mut first = signal.wait("first");
+        let mut second = signal.wait("second");
+        let mut third = signal.wait("third");
+
+        assert!(poll_once(&mut first).is_pending());
+        assert!(poll_once(&mut second).is_pending());
+        assert!(poll_once(&mut third).is_pending());
+        assert_eq!(signal.waiter_names().len(), 3);
+
+        drop(second);
+        let names = signal.waiter_names();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"first"));
+        assert!(!names.contains(&"second"));
+        assert!(names.contains(&"third"));
+
+        signal.signal(TestCommand::Start);
+        assert_eq!(
+            poll_once(&mut first),
+            core::task::Poll::Ready(TestCommand::Start)
+        );
+        assert_eq!(
+            poll_once(&mut third),
+            core::task::Poll::Ready(TestCommand::Start)
+        );
+    }
+
+    #[test]
+    async fn test_wait_timeout_frees_waiter() {
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 2>::new();
+
+        let res = signal
+            .wait_timeout("never", Duration::from_millis(10))
+            .await;
+        assert_eq!(res, None);
+        assert!(signal.waiter_names().is_empty());
+    }
+
+    #[test]
+    fn test_wait_take() {
+        use embassy_futures::poll_once;
+
+        /// Deliberately not `Clone`.
+        #[derive(Debug, PartialEq)]
+        struct Command(u8);
+
+        let signal = StickySignal::<NoopRawMutex, Command, 1>::new();
+        let mut waiter = signal.wait_take("take");
+        assert!(poll_once(&mut waiter).is_pending());
+
+        signal.signal(Command(1));
+        assert_eq!(poll_once(&mut waiter), core::task::Poll::Ready(Command(1)));
+        assert!(!signal.is_signaled());
+    }
+
+    #[test]
+    fn test_stream() {
+        use embassy_futures::poll_once;
+        use futures::StreamExt;
+
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 2>::new();
+        {
+            let stream = signal.stream("stream");
+            futures::pin_mut!(stream);
+
+            assert!(poll_once(stream.next()).is_pending());
+            signal.signal(TestCommand::Start);
+            assert_eq!(
+                poll_once(stream.next()),
+                core::task::Poll::Ready(Some(TestCommand::Start))
+            );
+
+            assert!(poll_once(stream.next()).is_pending());
+            assert_eq!(signal.waiter_names().len(), 1);
+        }
+
+        // dropping the stream drops its waiter
+        assert!(signal.waiter_names().is_empty());
+    }
+
+    #[test]
+    fn test_queue_overflow_drops_oldest() {
+        let queue = StickyQueue::<NoopRawMutex, u8, 1, 2>::new();
+        queue.signal(1);
+        queue.signal(2);
+        queue.signal(3);
+
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.try_take(), Some(2));
+        assert_eq!(queue.try_take(), Some(3));
+        assert_eq!(queue.try_take(), None);
+    }
+
+    #[test]
+    fn test_queue_fifo() {
+        use embassy_futures::poll_once;
+
+        let queue = StickyQueue::<NoopRawMutex, u8, 1, 4>::new();
+        let mut waiter = queue.wait("fifo");
+        assert!(poll_once(&mut waiter).is_pending());
+
+        queue.signal(1);
+        queue.signal(2);
+        assert_eq!(poll_once(&mut waiter), core::task::Poll::Ready(1));
+        assert_eq!(poll_once(queue.wait("fifo")), core::task::Poll::Ready(2));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_map() {
+        use embassy_futures::poll_once;
+
+        let signal = StickySignal::<NoopRawMutex, TestCommand, 2>::new();
+        let started = signal.map(|command| command == TestCommand::Start);
+        assert_eq!(started.peek(), None);
+
+        let mut waiter = core::pin::pin!(started.wait("started"));
+        assert!(poll_once(waiter.as_mut()).is_pending());
+
+        signal.signal(TestCommand::Stop);
+        assert_eq!(poll_once(waiter.as_mut()), core::task::Poll::Ready(false));
+        assert_eq!(started.peek(), Some(false));
     }
 }
@@ -0,0 +1,35 @@
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use watchy_rs::*;
+
+    #[test]
+    fn test_credentials_round_trip() {
+        let credentials = Credentials::new("network", "hunter2").unwrap();
+        assert_eq!(
+            Credentials::from_bytes(&credentials.to_bytes()),
+            Some(credentials)
+        );
+    }
+
+    #[test]
+    fn test_erased_region() {
+        assert_eq!(
+            Credentials::from_bytes(&[0xff; CREDENTIALS_RECORD_LEN]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_corrupt_length() {
+        let mut bytes = Credentials::new("network", "hunter2").unwrap().to_bytes();
+        bytes[4] = 0xff;
+        assert_eq!(Credentials::from_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn test_too_long() {
+        let ssid = "a network name over 32 characters";
+        assert!(Credentials::new(ssid, "").is_none());
+    }
+}
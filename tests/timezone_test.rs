@@ -0,0 +1,82 @@
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use time::{Date, Month, OffsetDateTime, Time, UtcOffset};
+    use watchy_rs::*;
+
+    fn utc(year: i32, month: Month, day: u8, hour: u8, minute: u8) -> OffsetDateTime {
+        Date::from_calendar_date(year, month, day)
+            .unwrap()
+            .with_time(Time::from_hms(hour, minute, 0).unwrap())
+            .assume_utc()
+    }
+
+    fn hours(hours: i8) -> UtcOffset {
+        UtcOffset::from_hms(hours, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_no_dst() {
+        let timezone = DstRule::None.timezone(hours(1));
+        assert_eq!(
+            timezone.offset_at(utc(2024, Month::July, 1, 12, 0)),
+            hours(1)
+        );
+    }
+
+    #[test]
+    fn test_eu_spring_forward() {
+        // the last sunday in march 2024 is the 31st, at 01:00 utc
+        let timezone = DstRule::Eu.timezone(hours(1));
+        assert_eq!(
+            timezone.offset_at(utc(2024, Month::March, 31, 0, 59)),
+            hours(1)
+        );
+        assert_eq!(
+            timezone.offset_at(utc(2024, Month::March, 31, 1, 0)),
+            hours(2)
+        );
+    }
+
+    #[test]
+    fn test_eu_fall_back() {
+        // the last sunday in october 2024 is the 27th, at 01:00 utc
+        let timezone = DstRule::Eu.timezone(hours(1));
+        assert_eq!(
+            timezone.offset_at(utc(2024, Month::October, 27, 0, 59)),
+            hours(2)
+        );
+        assert_eq!(
+            timezone.offset_at(utc(2024, Month::October, 27, 1, 0)),
+            hours(1)
+        );
+    }
+
+    #[test]
+    fn test_us_spring_forward() {
+        // the second sunday in march 2024 is the 10th, at 02:00 est
+        let timezone = DstRule::Us.timezone(hours(-5));
+        assert_eq!(
+            timezone.offset_at(utc(2024, Month::March, 10, 6, 59)),
+            hours(-5)
+        );
+        assert_eq!(
+            timezone.offset_at(utc(2024, Month::March, 10, 7, 0)),
+            hours(-4)
+        );
+    }
+
+    #[test]
+    fn test_us_fall_back() {
+        // the first sunday in november 2024 is the 3rd, at 02:00 edt
+        let timezone = DstRule::Us.timezone(hours(-5));
+        assert_eq!(
+            timezone.offset_at(utc(2024, Month::November, 3, 5, 59)),
+            hours(-4)
+        );
+        assert_eq!(
+            timezone.offset_at(utc(2024, Month::November, 3, 6, 0)),
+            hours(-5)
+        );
+    }
+}
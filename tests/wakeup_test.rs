@@ -0,0 +1,70 @@
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use watchy_rs::*;
+
+    #[test]
+    fn test_decode_bottom_left() {
+        assert_eq!(
+            decode_ext1_button(RTCIO_GPIO7_CHANNEL),
+            Ok(Button::BottomLeft)
+        );
+    }
+
+    #[test]
+    fn test_decode_top_left() {
+        assert_eq!(decode_ext1_button(RTCIO_GPIO6_CHANNEL), Ok(Button::TopLeft));
+    }
+
+    #[test]
+    fn test_decode_top_right() {
+        assert_eq!(
+            decode_ext1_button(RTCIO_GPIO0_CHANNEL),
+            Ok(Button::TopRight)
+        );
+    }
+
+    #[test]
+    fn test_decode_bottom_right() {
+        assert_eq!(
+            decode_ext1_button(RTCIO_GPIO8_CHANNEL),
+            Ok(Button::BottomRight)
+        );
+    }
+
+    #[test]
+    fn test_decode_combined() {
+        let mask = RTCIO_GPIO6_CHANNEL | RTCIO_GPIO7_CHANNEL;
+        assert_eq!(decode_ext1_button(mask), Err(mask));
+    }
+
+    #[test]
+    fn test_channels_are_distinct_bits() {
+        for (i, channel) in BUTTON_CHANNELS.iter().enumerate() {
+            assert_eq!(channel.count_ones(), 1);
+            assert!(BUTTON_CHANNELS[i + 1..]
+                .iter()
+                .all(|other| other != channel));
+        }
+    }
+
+    #[test]
+    fn test_decode_by_index() {
+        for button in [
+            Button::BottomLeft,
+            Button::TopLeft,
+            Button::TopRight,
+            Button::BottomRight,
+        ] {
+            assert_eq!(
+                decode_ext1_button(BUTTON_CHANNELS[button as usize]),
+                Ok(button)
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_empty() {
+        assert_eq!(decode_ext1_button(0), Err(0));
+    }
+}